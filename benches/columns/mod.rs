@@ -0,0 +1,275 @@
+//! Snapshot of the vertical pass of `stackblur::blur` before it was split into
+//! strips of columns, kept so the wide benchmark can compare against it. Every
+//! column is blurred in place, a full row apart from one pixel to the next. The
+//! vector kernels for RGBA lines are left out, so only RGB compares like for like.
+
+pub fn blur_in_place(data: &mut [u8], width: usize, height: usize, channels: usize, radius: usize) {
+    let stride = width * channels;
+    let mut processor = Processor::new(channels, radius);
+    for row in 0..height {
+        processor.blur_line(data, row * stride, channels, width);
+    }
+    for col in 0..width {
+        processor.blur_line(data, col * channels, stride, height);
+    }
+}
+
+enum Processor {
+    RGBA(RGBAProcessor),
+    RGB(RGBProcessor),
+}
+
+impl Processor {
+    fn new(channels: usize, radius: usize) -> Self {
+        match channels {
+            4 => Processor::RGBA(RGBAProcessor::new(radius)),
+            3 => Processor::RGB(RGBProcessor::new(radius)),
+            _ => unreachable!("the benchmarks only blur RGBA and RGB"),
+        }
+    }
+
+    fn blur_line(&mut self, data: &mut [u8], start: usize, step: usize, len: usize) {
+        match self {
+            Processor::RGBA(p) => p.blur_line(data, start, step, len),
+            Processor::RGB(p) => p.blur_line(data, start, step, len),
+        }
+    }
+}
+
+struct RGBAProcessor {
+    stack: Vec<[u8; 4]>,
+    r: usize,
+    div: usize,
+    rr12: usize,
+    mul: usize,
+    shg: usize,
+}
+
+impl RGBAProcessor {
+    fn new(radius: usize) -> Self {
+        let r = radius - 1;
+        Self {
+            stack: vec![[0; 4]; 2 * r + 1],
+            r,
+            div: 2 * r + 1,
+            rr12: (r + 1) * (r + 2) / 2,
+            mul: MUL_TABLE[r],
+            shg: SHG_TABLE[r],
+        }
+    }
+
+    // Blurs the `len` pixels starting at `start` and `step` bytes apart, in place.
+    // Pixels are only overwritten once they have been pushed onto the stack.
+    fn blur_line(&mut self, data: &mut [u8], start: usize, step: usize, len: usize) {
+        let last = len - 1;
+        let pixel = |data: &[u8], i: usize| -> [u8; 4] {
+            let i = start + step * i.min(last);
+            [data[i], data[i + 1], data[i + 2], data[i + 3]]
+        };
+
+        let first = pixel(data, 0);
+        self.stack[..=self.r].fill(first);
+        let mut stack_ptr = self.r;
+        let mut r_sum = first[0] as usize * self.rr12;
+        let mut g_sum = first[1] as usize * self.rr12;
+        let mut b_sum = first[2] as usize * self.rr12;
+        let mut a_sum = first[3] as usize * self.rr12;
+        let mut r_sum_in = 0;
+        let mut g_sum_in = 0;
+        let mut b_sum_in = 0;
+        let mut a_sum_in = 0;
+        let mut r_sum_out = first[0] as usize * (self.r + 1);
+        let mut g_sum_out = first[1] as usize * (self.r + 1);
+        let mut b_sum_out = first[2] as usize * (self.r + 1);
+        let mut a_sum_out = first[3] as usize * (self.r + 1);
+
+        for i in 1..=self.r {
+            let p = pixel(data, i);
+            self.stack[self.r + i] = p;
+            r_sum += p[0] as usize * (self.r + 1 - i);
+            g_sum += p[1] as usize * (self.r + 1 - i);
+            b_sum += p[2] as usize * (self.r + 1 - i);
+            a_sum += p[3] as usize * (self.r + 1 - i);
+            r_sum_in += p[0] as usize;
+            g_sum_in += p[1] as usize;
+            b_sum_in += p[2] as usize;
+            a_sum_in += p[3] as usize;
+        }
+
+        for x in 0..len {
+            let i = start + step * x;
+            data[i] = ((r_sum * self.mul) >> self.shg) as u8;
+            data[i + 1] = ((g_sum * self.mul) >> self.shg) as u8;
+            data[i + 2] = ((b_sum * self.mul) >> self.shg) as u8;
+            data[i + 3] = ((a_sum * self.mul) >> self.shg) as u8;
+
+            r_sum -= r_sum_out;
+            g_sum -= g_sum_out;
+            b_sum -= b_sum_out;
+            a_sum -= a_sum_out;
+
+            // The oldest entry leaves the window and the next pixel takes its slot
+            let stack_start = (stack_ptr + self.r + 1) % self.div;
+            let old = self.stack[stack_start];
+            r_sum_out -= old[0] as usize;
+            g_sum_out -= old[1] as usize;
+            b_sum_out -= old[2] as usize;
+            a_sum_out -= old[3] as usize;
+
+            let p = pixel(data, x + self.r + 1);
+            self.stack[stack_start] = p;
+            r_sum_in += p[0] as usize;
+            g_sum_in += p[1] as usize;
+            b_sum_in += p[2] as usize;
+            a_sum_in += p[3] as usize;
+            r_sum += r_sum_in;
+            g_sum += g_sum_in;
+            b_sum += b_sum_in;
+            a_sum += a_sum_in;
+
+            // The next pixel becomes the centre and moves to the outgoing half
+            stack_ptr = (stack_ptr + 1) % self.div;
+            let centre = self.stack[stack_ptr];
+            r_sum_out += centre[0] as usize;
+            g_sum_out += centre[1] as usize;
+            b_sum_out += centre[2] as usize;
+            a_sum_out += centre[3] as usize;
+            r_sum_in -= centre[0] as usize;
+            g_sum_in -= centre[1] as usize;
+            b_sum_in -= centre[2] as usize;
+            a_sum_in -= centre[3] as usize;
+        }
+    }
+}
+
+struct RGBProcessor {
+    stack: Vec<[u8; 3]>,
+    r: usize,
+    div: usize,
+    rr12: usize,
+    mul: usize,
+    shg: usize,
+}
+
+impl RGBProcessor {
+    fn new(radius: usize) -> Self {
+        let r = radius - 1;
+        Self {
+            stack: vec![[0; 3]; 2 * r + 1],
+            r,
+            div: 2 * r + 1,
+            rr12: (r + 1) * (r + 2) / 2,
+            mul: MUL_TABLE[r],
+            shg: SHG_TABLE[r],
+        }
+    }
+
+    // Blurs the `len` pixels starting at `start` and `step` bytes apart, in place.
+    // Pixels are only overwritten once they have been pushed onto the stack.
+    fn blur_line(&mut self, data: &mut [u8], start: usize, step: usize, len: usize) {
+        let last = len - 1;
+        let pixel = |data: &[u8], i: usize| -> [u8; 3] {
+            let i = start + step * i.min(last);
+            [data[i], data[i + 1], data[i + 2]]
+        };
+
+        let first = pixel(data, 0);
+        self.stack[..=self.r].fill(first);
+        let mut stack_ptr = self.r;
+        let mut r_sum = first[0] as usize * self.rr12;
+        let mut g_sum = first[1] as usize * self.rr12;
+        let mut b_sum = first[2] as usize * self.rr12;
+        let mut r_sum_in = 0;
+        let mut g_sum_in = 0;
+        let mut b_sum_in = 0;
+        let mut r_sum_out = first[0] as usize * (self.r + 1);
+        let mut g_sum_out = first[1] as usize * (self.r + 1);
+        let mut b_sum_out = first[2] as usize * (self.r + 1);
+
+        for i in 1..=self.r {
+            let p = pixel(data, i);
+            self.stack[self.r + i] = p;
+            r_sum += p[0] as usize * (self.r + 1 - i);
+            g_sum += p[1] as usize * (self.r + 1 - i);
+            b_sum += p[2] as usize * (self.r + 1 - i);
+            r_sum_in += p[0] as usize;
+            g_sum_in += p[1] as usize;
+            b_sum_in += p[2] as usize;
+        }
+
+        for x in 0..len {
+            let i = start + step * x;
+            data[i] = ((r_sum * self.mul) >> self.shg) as u8;
+            data[i + 1] = ((g_sum * self.mul) >> self.shg) as u8;
+            data[i + 2] = ((b_sum * self.mul) >> self.shg) as u8;
+
+            r_sum -= r_sum_out;
+            g_sum -= g_sum_out;
+            b_sum -= b_sum_out;
+
+            // The oldest entry leaves the window and the next pixel takes its slot
+            let stack_start = (stack_ptr + self.r + 1) % self.div;
+            let old = self.stack[stack_start];
+            r_sum_out -= old[0] as usize;
+            g_sum_out -= old[1] as usize;
+            b_sum_out -= old[2] as usize;
+
+            let p = pixel(data, x + self.r + 1);
+            self.stack[stack_start] = p;
+            r_sum_in += p[0] as usize;
+            g_sum_in += p[1] as usize;
+            b_sum_in += p[2] as usize;
+            r_sum += r_sum_in;
+            g_sum += g_sum_in;
+            b_sum += b_sum_in;
+
+            // The next pixel becomes the centre and moves to the outgoing half
+            stack_ptr = (stack_ptr + 1) % self.div;
+            let centre = self.stack[stack_ptr];
+            r_sum_out += centre[0] as usize;
+            g_sum_out += centre[1] as usize;
+            b_sum_out += centre[2] as usize;
+            r_sum_in -= centre[0] as usize;
+            g_sum_in -= centre[1] as usize;
+            b_sum_in -= centre[2] as usize;
+        }
+    }
+}
+
+#[rustfmt::skip]
+const MUL_TABLE: [usize; 256] = [
+    512,512,456,512,328,456,335,512,405,328,271,456,388,335,292,512,
+    454,405,364,328,298,271,496,456,420,388,360,335,312,292,273,512,
+    482,454,428,405,383,364,345,328,312,298,284,271,259,496,475,456,
+    437,420,404,388,374,360,347,335,323,312,302,292,282,273,265,512,
+    497,482,468,454,441,428,417,405,394,383,373,364,354,345,337,328,
+    320,312,305,298,291,284,278,271,265,259,507,496,485,475,465,456,
+    446,437,428,420,412,404,396,388,381,374,367,360,354,347,341,335,
+    329,323,318,312,307,302,297,292,287,282,278,273,269,265,261,512,
+    505,497,489,482,475,468,461,454,447,441,435,428,422,417,411,405,
+    399,394,389,383,378,373,368,364,359,354,350,345,341,337,332,328,
+    324,320,316,312,309,305,301,298,294,291,287,284,281,278,274,271,
+    268,265,262,259,257,507,501,496,491,485,480,475,470,465,460,456,
+    451,446,442,437,433,428,424,420,416,412,408,404,400,396,392,388,
+    385,381,377,374,370,367,363,360,357,354,350,347,344,341,338,335,
+    332,329,326,323,320,318,315,312,310,307,304,302,299,297,294,292,
+    289,287,285,282,280,278,275,273,271,269,267,265,263,261,259,257];
+
+#[rustfmt::skip]
+const SHG_TABLE: [usize; 256] = [
+     9, 11, 12, 13, 13, 14, 14, 15, 15, 15, 15, 16, 16, 16, 16, 17,
+    17, 17, 17, 17, 17, 17, 18, 18, 18, 18, 18, 18, 18, 18, 18, 19,
+    19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 19, 20, 20, 20,
+    20, 20, 20, 20, 20, 20, 20, 20, 20, 20, 20, 20, 20, 20, 20, 21,
+    21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 21,
+    21, 21, 21, 21, 21, 21, 21, 21, 21, 21, 22, 22, 22, 22, 22, 22,
+    22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22,
+    22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 22, 23,
+    23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23,
+    23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23,
+    23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23, 23,
+    23, 23, 23, 23, 23, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24,
+    24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24,
+    24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24,
+    24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24,
+    24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24 ];
@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use stackblur::stackblur;
+
+#[allow(clippy::all)]
+mod baseline;
+
+const WIDTH: usize = 512;
+const HEIGHT: usize = 512;
+const RADII: [usize; 9] = [1, 2, 4, 8, 16, 32, 64, 128, 254];
+
+fn image() -> Vec<u8> {
+    (0..WIDTH * HEIGHT * 4)
+        .map(|i| ((i * 7919) % 251) as u8)
+        .collect()
+}
+
+fn radius(c: &mut Criterion) {
+    let data = image();
+    let mut group = c.benchmark_group("radius");
+    group.sample_size(10);
+
+    for radius in RADII {
+        // Only time the rewrite against a baseline it agrees with
+        assert!(
+            stackblur::blur(&data, WIDTH, HEIGHT, &stackblur::Channels::RGBA, radius)
+                == baseline::blur(&data, WIDTH, HEIGHT, radius),
+            "blur differs from the baseline at radius {}",
+            radius
+        );
+        group.bench_with_input(BenchmarkId::new("blur", radius), &radius, |b, &radius| {
+            b.iter(|| stackblur::blur(&data, WIDTH, HEIGHT, &stackblur::Channels::RGBA, radius))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("baseline", radius),
+            &radius,
+            |b, &radius| b.iter(|| baseline::blur(&data, WIDTH, HEIGHT, radius)),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, radius);
+criterion_main!(benches);
@@ -0,0 +1,46 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use stackblur::stackblur;
+
+#[allow(clippy::all)]
+mod columns;
+
+// 8K UHD, where one row of RGBA pixels is 30 KiB apart from the next
+const WIDTH: usize = 7680;
+const HEIGHT: usize = 4320;
+const RADIUS: usize = 20;
+
+fn wide(c: &mut Criterion) {
+    let mut group = c.benchmark_group("8k");
+    group.sample_size(10);
+
+    for (name, channels) in [
+        ("rgba", stackblur::Channels::RGBA),
+        ("rgb", stackblur::Channels::RGB),
+    ] {
+        let cc = channels.count();
+        let mut data: Vec<u8> = (0..WIDTH * HEIGHT * cc)
+            .map(|i| ((i * 7919) % 251) as u8)
+            .collect();
+
+        // Only time the strips against a column walk they agree with
+        let mut walked = data.clone();
+        columns::blur_in_place(&mut walked, WIDTH, HEIGHT, cc, RADIUS);
+        assert!(
+            stackblur::blur(&data, WIDTH, HEIGHT, &channels, RADIUS) == walked,
+            "{} strips differ from the column walk",
+            name
+        );
+
+        group.bench_function(format!("{}/strips", name), |b| {
+            b.iter(|| stackblur::blur_in_place(&mut data, WIDTH, HEIGHT, &channels, RADIUS))
+        });
+        group.bench_function(format!("{}/columns", name), |b| {
+            b.iter(|| columns::blur_in_place(&mut data, WIDTH, HEIGHT, cc, RADIUS))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, wide);
+criterion_main!(benches);
@@ -1,736 +1,1295 @@
 pub mod stackblur {
-    // Somewhat optimized blur
-    pub fn blur(
-        data: &[u8],
+    #[cfg(feature = "rayon")]
+    mod parallel;
+    mod sample;
+    mod simd;
+    mod srgb;
+
+    use sample::sealed::Sealed;
+    pub use sample::Sample;
+
+    /// Somewhat optimized blur, of 8-bit, 16-bit or `f32` samples
+    ///
+    /// Every sample becomes the average of its neighbours, weighted by a triangle
+    /// reaching `radius - 1` pixels to each side. For 8-bit samples and radii up
+    /// to 256 the average is approximated with a multiply and shift. Otherwise it
+    /// is divided exactly, then rounded down for integer samples.
+    ///
+    /// Both passes run in place on the returned buffer, so a call allocates
+    /// `width * height * channels` samples for the output, a stack of
+    /// `2 * radius - 1` pixels and a scratch strip of 32 columns of the image.
+    /// Lines shorter than the radius are copied instead of stacked, so the stack
+    /// never outgrows twice the image.
+    pub fn blur<T: Sample>(
+        data: &[T],
         width: usize,
         height: usize,
         channels: &Channels,
         radius: usize,
-    ) -> Vec<u8> {
-        match channels {
-            Channels::RGBA => {
-                let a = RGBAHProcessor::new(data, width, height, radius)
-                    .flatten()
-                    .collect::<Vec<u8>>();
-                let mut out: Vec<u8> = vec![Default::default(); width * height * 4];
-                RGBAVProcessor::new(&a[..], width, height, radius)
-                    .enumerate()
-                    .for_each(|(i, item)| {
-                        out[((i % height) * width + (i / height)) * 4 + 0] = item[0];
-                        out[((i % height) * width + (i / height)) * 4 + 1] = item[1];
-                        out[((i % height) * width + (i / height)) * 4 + 2] = item[2];
-                        out[((i % height) * width + (i / height)) * 4 + 3] = item[3];
-                    });
-                out
-            }
-            Channels::RGB => {
-                let a = RGBHProcessor::new(data, width, height, radius)
-                    .flatten()
-                    .collect::<Vec<u8>>();
-                let mut out: Vec<u8> = vec![Default::default(); width * height * 3];
-                RGBVProcessor::new(&a[..], width, height, radius)
-                    .enumerate()
-                    .for_each(|(i, item)| {
-                        out[((i % height) * width + (i / height)) * 3 + 0] = item[0];
-                        out[((i % height) * width + (i / height)) * 3 + 1] = item[1];
-                        out[((i % height) * width + (i / height)) * 3 + 2] = item[2];
-                    });
-                out
+    ) -> Vec<T> {
+        let mut out = data[..width * height * channels.count()].to_vec();
+        blur_in_place(&mut out, width, height, channels, radius);
+        out
+    }
+
+    /// Same as [`blur`], but writes the result back into `data`.
+    ///
+    /// The only allocations are the `2 * radius - 1` pixel stack and a scratch
+    /// strip of 32 columns of the image.
+    pub fn blur_in_place<T: Sample>(
+        data: &mut [T],
+        width: usize,
+        height: usize,
+        channels: &Channels,
+        radius: usize,
+    ) {
+        blur_view_in_place(
+            &mut ImageViewMut::new(data, width, height, *channels),
+            radius,
+        );
+    }
+
+    /// Same as [`blur`], but reads from and writes to images with padded rows.
+    ///
+    /// `src` is copied row by row into `dst`, which is then blurred in place.
+    pub fn blur_view<T: Sample>(src: &ImageView<T>, dst: &mut ImageViewMut<T>, radius: usize) {
+        blur_view_with(src, dst, &Options::new(radius));
+    }
+
+    /// Same as [`blur_in_place`], but for images with padded rows.
+    pub fn blur_view_in_place<T: Sample>(image: &mut ImageViewMut<T>, radius: usize) {
+        blur_view_in_place_with(image, &Options::new(radius));
+    }
+
+    /// Settings shared by the `_with` entry points
+    ///
+    /// [`Options::new`] gives the settings used by the plain entry points, and
+    /// individual fields can be overridden from there with struct update syntax.
+    /// `T` is the sample type of the images they apply to.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Options<T = u8> {
+        /// Radius of the horizontal pass, 0 skips the pass
+        pub radius_x: usize,
+        /// Radius of the vertical pass, 0 skips the pass
+        pub radius_y: usize,
+        /// How the alpha channel, if any, weighs the colour channels
+        pub alpha: Alpha,
+        /// Transfer function of the colour samples
+        pub gamma: Gamma,
+        /// Pixels sampled past the edges of the image
+        pub edge: Edge<T>,
+        /// Channels to blur, bit `c` standing for channel `c`, and channels past
+        /// 63 following bit 63. The others keep their samples.
+        pub channel_mask: u64,
+        /// Number of threads to blur with, or 0 for the current rayon pool. The
+        /// pool for the last other count is kept for the next blur. Without the
+        /// `rayon` feature the blur always runs on the calling thread.
+        pub threads: usize,
+    }
+
+    impl<T> Options<T> {
+        /// Same radius for both passes, every channel blurred independently and
+        /// as encoded, edges clamped, on the calling thread
+        pub fn new(radius: usize) -> Self {
+            Options {
+                radius_x: radius,
+                radius_y: radius,
+                alpha: Alpha::Independent,
+                gamma: Gamma::Encoded,
+                edge: Edge::Clamp,
+                channel_mask: u64::MAX,
+                threads: 1,
             }
         }
     }
 
-    pub enum Channels {
-        RGBA,
-        RGB,
+    /// Treatment of the alpha channel of [`Channels::RGBA`] and
+    /// [`Channels::GrayAlpha`] images. Other layouts have no alpha channel.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Alpha {
+        /// Blur alpha like any other channel, so the colour of transparent pixels
+        /// bleeds into their neighbours
+        Independent,
+        /// Premultiply colours by alpha before blurring and divide them by the
+        /// blurred alpha afterwards, for images with straight alpha. The colours
+        /// are blurred in a copy at the precision of [`Gamma::Linear`], so nearly
+        /// transparent pixels keep their colour.
+        Premultiply,
+        /// Colours are already premultiplied by alpha, so every channel is blurred
+        /// as is and the result stays premultiplied
+        Premultiplied,
     }
 
-    struct RGBAHProcessor<'a> {
-        data: &'a [u8],
-        width: usize,
-        height: usize,
-        radius: usize,
-        current_index: usize,
-        current_row: usize,
-        r_store: Vec<u8>,
-        g_store: Vec<u8>,
-        b_store: Vec<u8>,
-        a_store: Vec<u8>,
-        r_sum: usize,
-        g_sum: usize,
-        b_sum: usize,
-        a_sum: usize,
-        w4: usize,
-        rr12: usize,
-        r_minus_1: usize,
-        w_minus_1: usize,
-        mul: usize,
-        shg: usize,
-    }
-
-    impl<'a> RGBAHProcessor<'a> {
-        fn new(data: &'a [u8], width: usize, height: usize, radius: usize) -> Self {
-            Self {
-                data,
-                width,
-                height,
-                radius,
-                current_index: 0,
-                current_row: 0,
-                r_store: vec![data[0]; radius],
-                g_store: vec![data[1]; radius],
-                b_store: vec![data[2]; radius],
-                a_store: vec![data[3]; radius],
-                r_sum: (data[0] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[4 * x + 0] as usize * (radius - 1 - x)
-                    }),
-                g_sum: (data[1] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[4 * x + 1] as usize * (radius - 1 - x)
-                    }),
-                b_sum: (data[2] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[4 * x + 2] as usize * (radius - 1 - x)
-                    }),
-                a_sum: (data[3] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[4 * x + 3] as usize * (radius - 1 - x)
-                    }),
-                w4: width * 4,
-                rr12: radius * (radius + 1) / 2,
-                r_minus_1: radius - 1,
-                w_minus_1: width - 1,
-                mul: MUL_TABLE[radius - 1],
-                shg: SHG_TABLE[radius - 1],
-            }
-        }
-    }
-
-    impl Iterator for RGBAHProcessor<'_> {
-        type Item = [u8; 4];
-        fn next(&mut self) -> Option<Self::Item> {
-            if self.current_index == self.width {
-                self.current_row += 1;
-                if self.current_row == self.height {
-                    return None;
-                }
-                self.current_index = 0;
-                let rw4 = self.current_row * self.w4;
-
-                self.r_store = vec![self.data[rw4 + 0]; self.radius];
-                self.g_store = vec![self.data[rw4 + 1]; self.radius];
-                self.b_store = vec![self.data[rw4 + 2]; self.radius];
-                self.a_store = vec![self.data[rw4 + 3]; self.radius];
-
-                self.r_sum = (self.data[rw4 + 0] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[rw4 + 4 * x + 0] as usize * (self.r_minus_1 - x)
-                    });
-                self.g_sum = (self.data[rw4 + 1] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[rw4 + 4 * x + 1] as usize * (self.r_minus_1 - x)
-                    });
-                self.b_sum = (self.data[rw4 + 2] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[rw4 + 4 * x + 2] as usize * (self.r_minus_1 - x)
-                    });
-                self.a_sum = (self.data[rw4 + 3] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[rw4 + 4 * x + 3] as usize * (self.r_minus_1 - x)
-                    });
+    /// How colour samples are blurred. Alpha always holds linear coverage.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Gamma {
+        /// Average the samples as they are
+        Encoded,
+        /// Samples are sRGB encoded, so decode them to linear light, average
+        /// that and encode the result. This avoids darkened edges and muddy
+        /// highlights, at the cost of a copy of the image holding 8-bit samples
+        /// at 16 bits and 16-bit samples as `f32`.
+        Linear,
+    }
+
+    /// Where the pixels past the ends of a line come from, in either pass
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Edge<T = u8> {
+        /// Repeat the edge pixel
+        Clamp,
+        /// Reflect the line at its ends, edge pixel included, so `c b a | a b c`
+        Mirror,
+        /// Continue from the other end of the line, for tileable textures
+        Wrap,
+        /// Pixels of these samples, in channel order. Layouts with fewer channels
+        /// use the leading ones and interleaved layouts with more repeat them
+        /// every four channels. For [`Gamma::Linear`] they are sRGB encoded too,
+        /// and for [`Alpha::Premultiply`] they have straight alpha.
+        Constant([T; 4]),
+    }
+
+    impl<T: Copy> Edge<T> {
+        // Index within a line of `len` pixels that position `i` takes its pixel
+        // from, or `None` for the constant
+        fn source(&self, i: isize, len: usize) -> Option<usize> {
+            let len = len as isize;
+            let i = match self {
+                Edge::Clamp => i.clamp(0, len - 1),
+                Edge::Mirror => match i.rem_euclid(2 * len) {
+                    i if i < len => i,
+                    i => 2 * len - 1 - i,
+                },
+                Edge::Wrap => i.rem_euclid(len),
+                Edge::Constant(_) => return (0..len).contains(&i).then_some(i as usize),
+            };
+            Some(i as usize)
+        }
+
+        // Calls `f` with every index of a line of `len` pixels that positions
+        // `first..=last` outside the line take their pixel from, `None` standing
+        // for the constant, along with the number of those positions and their sum
+        fn fold(
+            &self,
+            first: isize,
+            last: isize,
+            len: usize,
+            mut f: impl FnMut(Option<usize>, i128, i128),
+        ) {
+            // Positions congruent to `i` modulo `period`
+            let every = |i: usize, period: usize| {
+                let (first, last, period) = (first as i128, last as i128, period as i128);
+                let lowest = first + (i as i128 - first).rem_euclid(period);
+                let count = match lowest > last {
+                    true => 0,
+                    false => (last - lowest) / period + 1,
+                };
+                (count, count * lowest + period * count * (count - 1) / 2)
+            };
+            match self {
+                Edge::Clamp => f(self.source(first, len), every(0, 1).0, every(0, 1).1),
+                Edge::Constant(_) => f(None, every(0, 1).0, every(0, 1).1),
+                Edge::Wrap => {
+                    for i in 0..len {
+                        let (count, total) = every(i, len);
+                        f(Some(i), count, total);
+                    }
+                }
+                Edge::Mirror => {
+                    for i in 0..len {
+                        let (count, total) = every(i, 2 * len);
+                        let (mirrored, mirrored_total) = every(2 * len - 1 - i, 2 * len);
+                        f(Some(i), count + mirrored, total + mirrored_total);
+                    }
+                }
             }
+        }
 
-            let rw4 = self.current_row * self.w4;
-            let store_len = self.r_store.len();
+        // Same mode, with the samples of a constant converted by `convert`
+        fn map<U>(self, convert: impl FnOnce([T; 4]) -> [U; 4]) -> Edge<U> {
+            match self {
+                Edge::Clamp => Edge::Clamp,
+                Edge::Mirror => Edge::Mirror,
+                Edge::Wrap => Edge::Wrap,
+                Edge::Constant(colour) => Edge::Constant(convert(colour)),
+            }
+        }
+    }
 
-            self.r_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[rw4
-                        + 4 * (if self.current_index + rad < self.width {
-                            self.current_index + rad
-                        } else {
-                            self.w_minus_1
-                        })
-                        + 0] as usize
-                })
-                .sum::<usize>();
-            self.r_sum -= (&self.r_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
-
-            self.g_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[rw4
-                        + 4 * (if self.current_index + rad < self.width {
-                            self.current_index + rad
-                        } else {
-                            self.w_minus_1
-                        })
-                        + 1] as usize
-                })
-                .sum::<usize>();
-            self.g_sum -= (&self.g_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
-
-            self.b_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[rw4
-                        + 4 * (if self.current_index + rad < self.width {
-                            self.current_index + rad
-                        } else {
-                            self.w_minus_1
-                        })
-                        + 2] as usize
-                })
-                .sum::<usize>();
-            self.b_sum -= (&self.b_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
-
-            self.a_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[rw4
-                        + 4 * (if self.current_index + rad < self.width {
-                            self.current_index + rad
-                        } else {
-                            self.w_minus_1
-                        })
-                        + 3] as usize
+    /// Same as [`blur_view`], with the settings in [`Options`].
+    pub fn blur_view_with<T: Sample>(
+        src: &ImageView<T>,
+        dst: &mut ImageViewMut<T>,
+        options: &Options<T>,
+    ) {
+        assert_same_shape(src, dst);
+        for y in 0..src.height {
+            dst.row_mut(y).copy_from_slice(src.row(y));
+        }
+        blur_view_in_place_with(dst, options);
+    }
+
+    /// Same as [`blur_view_in_place`], with the settings in [`Options`].
+    pub fn blur_view_in_place_with<T: Sample>(image: &mut ImageViewMut<T>, options: &Options<T>) {
+        // Converting is lossy for integer samples, so skip it when neither pass
+        // changes the image
+        let blurs = options.radius_x > 1 || options.radius_y > 1;
+        let premultiply = match options.alpha {
+            Alpha::Premultiply if blurs => image.channels.alpha(),
+            _ => None,
+        };
+        if (options.gamma == Gamma::Linear && blurs) || premultiply.is_some() {
+            return blur_wide(image, options, premultiply);
+        }
+        blur_passes(image, options);
+    }
+
+    // Blurs a copy of `image` in the wider samples of `T::Linear`, decoded to
+    // linear light for `Gamma::Linear` and premultiplied by the alpha channel
+    // `premultiply` if any, then narrows the result back into `image` once.
+    // Premultiplying in place would round away most of the colour of nearly
+    // transparent pixels.
+    fn blur_wide<T: Sample>(
+        image: &mut ImageViewMut<T>,
+        options: &Options<T>,
+        premultiply: Option<usize>,
+    ) {
+        let (width, height, channels) = (image.width, image.height, image.channels);
+        let cc = channels.count();
+        let alpha = channels.alpha();
+        let srgb = |c: usize| options.gamma == Gamma::Linear && Some(c) != alpha;
+        let decode = |c: usize, sample: T| match srgb(c) {
+            true => sample.decode_srgb(),
+            false => sample.widen(),
+        };
+        let mut linear = Vec::with_capacity(width * height * cc);
+        for y in 0..height {
+            let row = image.row(y).iter().enumerate();
+            linear.extend(row.map(|(i, &sample)| decode(i % cc, sample)));
+        }
+        let mask = options.channel_mask;
+        let mut wide_mask = mask;
+        let mut edge = options
+            .edge
+            .map(|colour| std::array::from_fn(|c| decode(c, colour[c])));
+        if let Some(alpha) = premultiply {
+            let mut wide = ImageViewMut::new(&mut linear, width, height, channels);
+            convert_colours(&mut wide, alpha, mask, T::Linear::premultiply);
+            edge = edge.map(|colour| {
+                std::array::from_fn(|c| match c == alpha {
+                    true => colour[c],
+                    false => colour[c].premultiply(colour[alpha]),
                 })
-                .sum::<usize>();
-            self.a_sum -= (&self.a_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
+            });
+            // Alpha is blurred as is, so it comes out the same as with the other
+            // modes, and only the colours are blurred wide
+            wide_mask &= !(1 << alpha);
+            if blurs_channel(mask, alpha) {
+                let channel_mask = 1 << alpha;
+                blur_passes(
+                    image,
+                    &Options {
+                        channel_mask,
+                        ..*options
+                    },
+                );
+            }
+        }
+        blur_passes(
+            &mut ImageViewMut::new(&mut linear, width, height, channels),
+            &Options {
+                radius_x: options.radius_x,
+                radius_y: options.radius_y,
+                alpha: options.alpha,
+                gamma: options.gamma,
+                edge,
+                channel_mask: wide_mask,
+                threads: options.threads,
+            },
+        );
+        if let Some(alpha) = premultiply {
+            // Divide by the alpha that ends up in `image`, so pixels left fully
+            // transparent lose their colour
+            for (y, linear) in linear.chunks_exact_mut(width * cc).enumerate() {
+                let pixels = linear
+                    .chunks_exact_mut(cc)
+                    .zip(image.row(y).chunks_exact(cc));
+                for (pixel, blurred) in pixels {
+                    pixel[alpha] = blurred[alpha].widen();
+                }
+            }
+            let mut wide = ImageViewMut::new(&mut linear, width, height, channels);
+            convert_colours(&mut wide, alpha, wide_mask, T::Linear::unpremultiply);
+        }
+        for (y, linear) in linear.chunks_exact(width * cc).enumerate() {
+            for (i, (sample, &linear)) in image.row_mut(y).iter_mut().zip(linear).enumerate() {
+                let c = i % cc;
+                if !blurs_channel(wide_mask, c) {
+                    continue;
+                }
+                *sample = match srgb(c) {
+                    true => T::encode_srgb(linear),
+                    false => T::narrow(linear),
+                };
+            }
+        }
+    }
+
+    // Replaces every colour sample of the channels in `mask` with
+    // `convert(colour, alpha)`, given the index of the alpha channel
+    fn convert_colours<T: Sample>(
+        image: &mut ImageViewMut<T>,
+        alpha: usize,
+        mask: u64,
+        convert: impl Fn(T, T) -> T,
+    ) {
+        let cc = image.channels.count();
+        for y in 0..image.height {
+            for pixel in image.row_mut(y).chunks_exact_mut(cc) {
+                let a = pixel[alpha];
+                for (c, sample) in pixel.iter_mut().enumerate() {
+                    if c != alpha && blurs_channel(mask, c) {
+                        *sample = convert(*sample, a);
+                    }
+                }
+            }
+        }
+    }
+
+    // Both passes of `blur_view_in_place_with`, on the colours as they are
+    fn blur_passes<T: Sample>(image: &mut ImageViewMut<T>, options: &Options<T>) {
+        assert!(
+            image.channels.count() > 0,
+            "Images need at least one channel"
+        );
+        #[cfg(feature = "rayon")]
+        match options.threads {
+            1 => {}
+            0 => return parallel::blur(image, options),
+            threads => return parallel::install(threads, || parallel::blur(image, options)),
+        }
 
-            let rw4_ci4 = rw4 + 4 * self.current_index;
+        let (width, height, stride) = (image.width, image.height, image.stride);
+        let cc = image.channels.count();
+        if options.radius_x > 0 {
+            let mut processor = Processor::new(
+                image.channels,
+                options.radius_x,
+                options.edge,
+                options.channel_mask,
+            );
+            for row in 0..height {
+                processor.blur_line(image.data, row * stride, cc, width);
+            }
+        }
 
-            let r = self.data[rw4_ci4 + 0];
-            let g = self.data[rw4_ci4 + 1];
-            let b = self.data[rw4_ci4 + 2];
-            let a = self.data[rw4_ci4 + 3];
+        if options.radius_y > 0 {
+            let mut processor = Processor::new(
+                image.channels,
+                options.radius_y,
+                options.edge,
+                options.channel_mask,
+            );
+            let mut scratch = vec![];
+            for first in (0..width).step_by(STRIP_WIDTH) {
+                let cols = first * cc..(first + STRIP_WIDTH).min(width) * cc;
+                let mut rows: Vec<&mut [T]> = image
+                    .data
+                    .chunks_mut(stride)
+                    .take(height)
+                    .map(|row| &mut row[cols.clone()])
+                    .collect();
+                blur_strip(&mut processor, &mut rows, &mut scratch);
+            }
+        }
+    }
 
-            self.r_store.push(r);
-            self.g_store.push(g);
-            self.b_store.push(b);
-            self.a_store.push(a);
+    // Columns blurred together in the vertical pass. Walking a single column
+    // touches one cache line per row; a strip is copied into a column-major
+    // scratch buffer instead, so the image is read and written row by row.
+    const STRIP_WIDTH: usize = 32;
+
+    // Vertical pass over a strip of columns, given as one slice per image row
+    fn blur_strip<T: Sample>(
+        processor: &mut Processor<T>,
+        rows: &mut [&mut [T]],
+        scratch: &mut Vec<T>,
+    ) {
+        match processor {
+            Processor::One(_) => blur_strip_of::<T, 1>(processor, rows, scratch),
+            Processor::Two(_) => blur_strip_of::<T, 2>(processor, rows, scratch),
+            Processor::Three(_) => blur_strip_of::<T, 3>(processor, rows, scratch),
+            Processor::Vector(..) | Processor::Four(_) => {
+                blur_strip_of::<T, 4>(processor, rows, scratch)
+            }
+            Processor::Interleaved(_) => {
+                let cc = processor.count();
+                blur_strip_by(processor, rows, scratch, cc, |dst, src| {
+                    dst.copy_from_slice(src)
+                });
+            }
+        }
+    }
 
-            self.current_index += 1;
+    // Pixels are moved as `[T; CC]` so each copy compiles to a single load and
+    // store rather than a call to memcpy
+    fn blur_strip_of<T: Sample, const CC: usize>(
+        processor: &mut Processor<T>,
+        rows: &mut [&mut [T]],
+        scratch: &mut Vec<T>,
+    ) {
+        blur_strip_by(processor, rows, scratch, CC, |dst, src| {
+            let pixel: [T; CC] = src.try_into().unwrap();
+            dst.copy_from_slice(&pixel);
+        });
+    }
 
-            Some([
-                ((self.r_sum * self.mul) >> self.shg) as u8,
-                ((self.g_sum * self.mul) >> self.shg) as u8,
-                ((self.b_sum * self.mul) >> self.shg) as u8,
-                ((self.a_sum * self.mul) >> self.shg) as u8,
-            ])
+    // Copies the strip into `scratch` with `copy`, one pixel of `cc` samples at a
+    // time, blurs every column and copies it back
+    #[inline(always)]
+    fn blur_strip_by<T: Sample>(
+        processor: &mut Processor<T>,
+        rows: &mut [&mut [T]],
+        scratch: &mut Vec<T>,
+        cc: usize,
+        copy: impl Fn(&mut [T], &[T]),
+    ) {
+        let height = rows.len();
+        let cols = rows.first().map_or(0, |row| row.len() / cc);
+        scratch.resize(cols * height * cc, T::default());
+
+        for (y, row) in rows.iter().enumerate() {
+            for (col, pixel) in row.chunks_exact(cc).enumerate() {
+                let at = (col * height + y) * cc;
+                copy(&mut scratch[at..at + cc], pixel);
+            }
+        }
+        for col in 0..cols {
+            processor.blur_line(scratch, col * height * cc, cc, height);
+        }
+        for (y, row) in rows.iter_mut().enumerate() {
+            for (col, pixel) in row.chunks_exact_mut(cc).enumerate() {
+                let at = (col * height + y) * cc;
+                copy(pixel, &scratch[at..at + cc]);
+            }
         }
     }
 
-    struct RGBAVProcessor<'a> {
-        data: &'a [u8],
+    /// Same as [`blur`], but splits both passes across `threads` threads.
+    ///
+    /// With `threads == 0` the work runs on the current rayon pool. The output is
+    /// identical to the single-threaded one.
+    #[cfg(feature = "rayon")]
+    pub fn blur_parallel<T: Sample>(
+        data: &[T],
         width: usize,
         height: usize,
+        channels: &Channels,
         radius: usize,
-        current_index: usize,
-        current_col: usize,
-        r_store: Vec<u8>,
-        g_store: Vec<u8>,
-        b_store: Vec<u8>,
-        a_store: Vec<u8>,
-        r_sum: usize,
-        g_sum: usize,
-        b_sum: usize,
-        a_sum: usize,
-        w4: usize,
-        rr12: usize,
-        r_minus_1: usize,
-        h_minus_1: usize,
-        mul: usize,
-        shg: usize,
-    }
-
-    impl<'a> RGBAVProcessor<'a> {
-        fn new(data: &'a [u8], width: usize, height: usize, radius: usize) -> Self {
-            Self {
-                data,
-                width,
-                height,
-                radius,
-                current_index: 0,
-                current_col: 0,
-                r_store: vec![data[0]; radius],
-                g_store: vec![data[1]; radius],
-                b_store: vec![data[2]; radius],
-                a_store: vec![data[3]; radius],
-                r_sum: (data[0] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[width * 4 * x + 0] as usize * (radius - 1 - x)
-                    }),
-                g_sum: (data[1] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[width * 4 * x + 1] as usize * (radius - 1 - x)
-                    }),
-                b_sum: (data[2] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[width * 4 * x + 2] as usize * (radius - 1 - x)
-                    }),
-                a_sum: (data[3] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[width * 4 * x + 3] as usize * (radius - 1 - x)
-                    }),
-                w4: width * 4,
-                rr12: radius * (radius + 1) / 2,
-                r_minus_1: radius - 1,
-                h_minus_1: height - 1,
-                mul: MUL_TABLE[radius - 1],
-                shg: SHG_TABLE[radius - 1],
-            }
-        }
-    }
-
-    impl Iterator for RGBAVProcessor<'_> {
-        type Item = [u8; 4];
-        fn next(&mut self) -> Option<Self::Item> {
-            if self.current_index == self.height {
-                self.current_col += 1;
-                if self.current_col == self.width {
-                    return None;
-                }
-                self.current_index = 0;
-                let c4 = self.current_col * 4;
-
-                self.r_store = vec![self.data[c4 + 0]; self.radius];
-                self.g_store = vec![self.data[c4 + 1]; self.radius];
-                self.b_store = vec![self.data[c4 + 2]; self.radius];
-                self.a_store = vec![self.data[c4 + 3]; self.radius];
-
-                self.r_sum = (self.data[c4 + 0] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[c4 + self.w4 * x + 0] as usize * (self.r_minus_1 - x)
-                    });
-                self.g_sum = (self.data[c4 + 1] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[c4 + self.w4 * x + 1] as usize * (self.r_minus_1 - x)
-                    });
-                self.b_sum = (self.data[c4 + 2] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[c4 + self.w4 * x + 2] as usize * (self.r_minus_1 - x)
-                    });
-                self.a_sum = (self.data[c4 + 3] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[c4 + self.w4 * x + 3] as usize * (self.r_minus_1 - x)
-                    });
-            }
+        threads: usize,
+    ) -> Vec<T> {
+        let mut out = data[..width * height * channels.count()].to_vec();
+        blur_view_in_place_parallel(
+            &mut ImageViewMut::new(&mut out, width, height, *channels),
+            radius,
+            threads,
+        );
+        out
+    }
 
-            let c4 = self.current_col * 4;
-            let store_len = self.r_store.len();
+    /// Same as [`blur_view_in_place`], but splits both passes across `threads`
+    /// threads, or the current rayon pool when `threads == 0`.
+    #[cfg(feature = "rayon")]
+    pub fn blur_view_in_place_parallel<T: Sample>(
+        image: &mut ImageViewMut<T>,
+        radius: usize,
+        threads: usize,
+    ) {
+        blur_view_in_place_with(
+            image,
+            &Options {
+                threads,
+                ..Options::new(radius)
+            },
+        );
+    }
 
-            self.r_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[c4
-                        + self.w4
-                            * (if self.current_index + rad < self.height {
-                                self.current_index + rad
-                            } else {
-                                self.h_minus_1
-                            })
-                        + 0] as usize
-                })
-                .sum::<usize>();
-            self.r_sum -= (&self.r_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
-
-            self.g_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[c4
-                        + self.w4
-                            * (if self.current_index + rad < self.height {
-                                self.current_index + rad
-                            } else {
-                                self.h_minus_1
-                            })
-                        + 1] as usize
-                })
-                .sum::<usize>();
-            self.g_sum -= (&self.g_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
-
-            self.b_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[c4
-                        + self.w4
-                            * (if self.current_index + rad < self.height {
-                                self.current_index + rad
-                            } else {
-                                self.h_minus_1
-                            })
-                        + 2] as usize
-                })
-                .sum::<usize>();
-            self.b_sum -= (&self.b_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
-
-            self.a_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[c4
-                        + self.w4
-                            * (if self.current_index + rad < self.height {
-                                self.current_index + rad
-                            } else {
-                                self.h_minus_1
-                            })
-                        + 3] as usize
-                })
-                .sum::<usize>();
-            self.a_sum -= (&self.a_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
+    /// Blurs only the pixels inside `region`, leaving the rest of `image` untouched.
+    ///
+    /// Pixels around the region are sampled just like in a full blur, so its edges
+    /// blend into the surrounding image instead of being clamped. The region is
+    /// clipped to the image, and a copy of it grown by `radius` on each side is
+    /// used as scratch.
+    pub fn blur_region<T: Sample>(image: &mut ImageViewMut<T>, region: &Rect, radius: usize) {
+        blur_region_with(image, region, &Options::new(radius));
+    }
+
+    /// Same as [`blur_region`], with the settings in [`Options`].
+    ///
+    /// The scratch copy is grown by `radius_x` on the left and right and by
+    /// `radius_y` on the top and bottom. With [`Edge::Wrap`] it spans the whole
+    /// width or height of the image once it reaches one of its edges.
+    pub fn blur_region_with<T: Sample>(
+        image: &mut ImageViewMut<T>,
+        region: &Rect,
+        options: &Options<T>,
+    ) {
+        let cc = image.channels.count();
+        let x1 = region.x.saturating_add(region.width).min(image.width);
+        let y1 = region.y.saturating_add(region.height).min(image.height);
+        let x0 = region.x.min(x1);
+        let y0 = region.y.min(y1);
+        if x0 == x1 || y0 == y1 {
+            return;
+        }
+
+        // Nothing further than the radius of a pass from the region contributes to it
+        let mut left = x0.saturating_sub(options.radius_x);
+        let mut top = y0.saturating_sub(options.radius_y);
+        let mut right = x1.saturating_add(options.radius_x).min(image.width);
+        let mut bottom = y1.saturating_add(options.radius_y).min(image.height);
+        // Wrapping past one edge of the image reads from the opposite one, so
+        // the copy has to span the whole axis to wrap the same way
+        if matches!(options.edge, Edge::Wrap) {
+            if left == 0 || right == image.width {
+                (left, right) = (0, image.width);
+            }
+            if top == 0 || bottom == image.height {
+                (top, bottom) = (0, image.height);
+            }
+        }
+        let width = right - left;
 
-            let c4w4ci = c4 + self.w4 * self.current_index;
+        let mut scratch = Vec::with_capacity(width * (bottom - top) * cc);
+        for y in top..bottom {
+            scratch.extend_from_slice(&image.row(y)[left * cc..right * cc]);
+        }
+        blur_view_in_place_with(
+            &mut ImageViewMut::new(&mut scratch, width, bottom - top, image.channels),
+            options,
+        );
+
+        for y in y0..y1 {
+            let start = ((y - top) * width + x0 - left) * cc;
+            image.row_mut(y)[x0 * cc..x1 * cc]
+                .copy_from_slice(&scratch[start..start + (x1 - x0) * cc]);
+        }
+    }
 
-            let r = self.data[c4w4ci + 0];
-            let g = self.data[c4w4ci + 1];
-            let b = self.data[c4w4ci + 2];
-            let a = self.data[c4w4ci + 3];
+    /// Blurs every pixel of `image` by its own radius, read from `mask`.
+    ///
+    /// A mask sample of 0 leaves the pixel untouched and 255 blurs it with
+    /// `radius`, with the radius growing linearly in between. `mask` is a
+    /// [`Channels::Gray`] image of the same size as `image`.
+    ///
+    /// The image is blurred in full at a series of radii, each about 1.4 times
+    /// the last, and every pixel mixes the two blurs nearest to its radius. Three
+    /// copies of the image are used as scratch.
+    pub fn blur_masked<T: Sample>(image: &mut ImageViewMut<T>, mask: &ImageView, radius: usize) {
+        blur_masked_with(image, mask, &Options::new(radius));
+    }
 
-            self.r_store.push(r);
-            self.g_store.push(g);
-            self.b_store.push(b);
-            self.a_store.push(a);
+    /// Same as [`blur_masked`], with the settings in [`Options`]. A mask
+    /// sample of 255 blurs with `radius_x` and `radius_y`, and smaller samples
+    /// scale both.
+    pub fn blur_masked_with<T: Sample>(
+        image: &mut ImageViewMut<T>,
+        mask: &ImageView,
+        options: &Options<T>,
+    ) {
+        assert!(
+            mask.width == image.width && mask.height == image.height,
+            "Mask and image differ in size"
+        );
+        assert!(mask.channels == Channels::Gray, "Mask isn't grayscale");
+        let rows = mask.height.saturating_sub(1).saturating_mul(mask.stride);
+        assert!(
+            mask.height == 0 || mask.data.len() >= rows.saturating_add(mask.width),
+            "Mask data is shorter than its rows"
+        );
+        let (width, height, channels) = (image.width, image.height, image.channels);
+        let cc = channels.count();
+        let max = options.radius_x.max(options.radius_y);
+        if max <= 1 || width == 0 {
+            return;
+        }
 
-            self.current_index += 1;
+        let mut source = Vec::with_capacity(width * height * cc);
+        for y in 0..height {
+            source.extend_from_slice(image.row(y));
+        }
+        // Radius 1 leaves the image as it is
+        let mut lower = source.clone();
+        let mut upper = vec![];
+        for pair in mask_radii(max).windows(2) {
+            let (low, high) = (pair[0], pair[1]);
+            upper.clone_from(&source);
+            blur_view_in_place_with(
+                &mut ImageViewMut::new(&mut upper, width, height, channels),
+                &Options {
+                    radius_x: (options.radius_x * high + max / 2) / max,
+                    radius_y: (options.radius_y * high + max / 2) / max,
+                    ..*options
+                },
+            );
+
+            for y in 0..height {
+                let rows = (y * width * cc..).step_by(cc).zip(mask.row(y));
+                let pixels = image.row_mut(y).chunks_exact_mut(cc).zip(rows);
+                for (pixel, (at, &m)) in pixels {
+                    let radius = (m as usize * max) as f32 / 255.0;
+                    if radius > low as f32 && radius <= high as f32 {
+                        let t = (radius - low as f32) / (high - low) as f32;
+                        for (c, sample) in pixel.iter_mut().enumerate() {
+                            *sample = lower[at + c].mix(upper[at + c], t);
+                        }
+                    }
+                }
+            }
+            std::mem::swap(&mut lower, &mut upper);
+        }
+    }
 
-            Some([
-                ((self.r_sum * self.mul) >> self.shg) as u8,
-                ((self.g_sum * self.mul) >> self.shg) as u8,
-                ((self.b_sum * self.mul) >> self.shg) as u8,
-                ((self.a_sum * self.mul) >> self.shg) as u8,
-            ])
+    // Radii a masked blur up to `max` blurs with, from 1 up, each about √2 times
+    // the last
+    fn mask_radii(max: usize) -> Vec<usize> {
+        let mut radii = vec![1];
+        let mut radius = 1;
+        while radius < max {
+            radius = ((radius as f64 * std::f64::consts::SQRT_2).round() as usize)
+                .clamp(radius + 1, max);
+            radii.push(radius);
         }
+        radii
     }
 
-    struct RGBHProcessor<'a> {
-        data: &'a [u8],
+    /// Same as [`blur`], but returns an error instead of panicking on malformed input.
+    pub fn try_blur<T: Sample>(
+        data: &[T],
         width: usize,
         height: usize,
+        channels: &Channels,
         radius: usize,
-        current_index: usize,
-        current_row: usize,
-        r_store: Vec<u8>,
-        g_store: Vec<u8>,
-        b_store: Vec<u8>,
-        r_sum: usize,
-        g_sum: usize,
-        b_sum: usize,
-        w3: usize,
-        rr12: usize,
-        r_minus_1: usize,
-        w_minus_1: usize,
-        mul: usize,
-        shg: usize,
-    }
-
-    impl<'a> RGBHProcessor<'a> {
-        fn new(data: &'a [u8], width: usize, height: usize, radius: usize) -> Self {
-            Self {
-                data,
-                width,
-                height,
-                radius,
-                current_index: 0,
-                current_row: 0,
-                r_store: vec![data[0]; radius],
-                g_store: vec![data[1]; radius],
-                b_store: vec![data[2]; radius],
-                r_sum: (data[0] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[3 * x + 0] as usize * (radius - 1 - x)
-                    }),
-                g_sum: (data[1] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[3 * x + 1] as usize * (radius - 1 - x)
-                    }),
-                b_sum: (data[2] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[3 * x + 2] as usize * (radius - 1 - x)
-                    }),
-                w3: width * 3,
-                rr12: radius * (radius + 1) / 2,
-                r_minus_1: radius - 1,
-                w_minus_1: width - 1,
-                mul: MUL_TABLE[radius - 1],
-                shg: SHG_TABLE[radius - 1],
-            }
-        }
-    }
-
-    impl Iterator for RGBHProcessor<'_> {
-        type Item = [u8; 3];
-        fn next(&mut self) -> Option<Self::Item> {
-            if self.current_index == self.width {
-                self.current_row += 1;
-                if self.current_row == self.height {
-                    return None;
-                }
-                self.current_index = 0;
-                let rw3 = self.current_row * self.w3;
-
-                self.r_store = vec![self.data[rw3 + 0]; self.radius];
-                self.g_store = vec![self.data[rw3 + 1]; self.radius];
-                self.b_store = vec![self.data[rw3 + 2]; self.radius];
-
-                self.r_sum = (self.data[rw3 + 0] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[rw3 + 3 * x + 0] as usize * (self.r_minus_1 - x)
-                    });
-                self.g_sum = (self.data[rw3 + 1] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[rw3 + 3 * x + 1] as usize * (self.r_minus_1 - x)
-                    });
-                self.b_sum = (self.data[rw3 + 2] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[rw3 + 3 * x + 2] as usize * (self.r_minus_1 - x)
-                    });
+    ) -> Result<Vec<T>, BlurError> {
+        let mut out = data.to_vec();
+        try_blur_in_place(&mut out, width, height, channels, radius)?;
+        Ok(out)
+    }
+
+    /// Same as [`blur_in_place`], but returns an error instead of panicking on
+    /// malformed input. `data` must hold exactly `width * height` pixels.
+    pub fn try_blur_in_place<T: Sample>(
+        data: &mut [T],
+        width: usize,
+        height: usize,
+        channels: &Channels,
+        radius: usize,
+    ) -> Result<(), BlurError> {
+        let expected = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(channels.count()))
+            .ok_or(BlurError::Overflow)?;
+        if data.len() != expected {
+            return Err(BlurError::BufferSize {
+                expected,
+                actual: data.len(),
+            });
+        }
+        try_blur_view_in_place_with(
+            &mut ImageViewMut::new(data, width, height, *channels),
+            &Options::new(radius),
+        )
+    }
+
+    /// Same as [`blur_view_in_place_with`], but returns an error instead of
+    /// panicking on malformed input. The last row doesn't need to be padded.
+    pub fn try_blur_view_in_place_with<T: Sample>(
+        image: &mut ImageViewMut<T>,
+        options: &Options<T>,
+    ) -> Result<(), BlurError> {
+        check_image(image, options)?;
+        blur_view_in_place_with(image, options);
+        Ok(())
+    }
+
+    /// Same as [`blur_region_with`], but returns an error instead of panicking on
+    /// malformed input.
+    pub fn try_blur_region_with<T: Sample>(
+        image: &mut ImageViewMut<T>,
+        region: &Rect,
+        options: &Options<T>,
+    ) -> Result<(), BlurError> {
+        check_image(image, options)?;
+        blur_region_with(image, region, options);
+        Ok(())
+    }
+
+    /// Why a `try_` function refused to blur an image
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum BlurError {
+        /// The buffer holds `actual` samples where the image needs `expected`
+        BufferSize { expected: usize, actual: usize },
+        /// Rows start `stride` samples apart but hold `row` samples of pixels each
+        Stride { stride: usize, row: usize },
+        /// The image is zero pixels wide or high, or has no channels
+        ZeroDimensions,
+        /// The radius is too large for the accumulators
+        InvalidRadius(usize),
+        /// The number of samples in the image doesn't fit in a `usize`
+        Overflow,
+    }
+
+    impl std::fmt::Display for BlurError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                BlurError::BufferSize { expected, actual } => write!(
+                    f,
+                    "Buffer holds {} samples but the image needs {}",
+                    actual, expected
+                ),
+                BlurError::Stride { stride, row } => write!(
+                    f,
+                    "Stride of {} samples is shorter than a row of {} samples",
+                    stride, row
+                ),
+                BlurError::ZeroDimensions => write!(f, "Image has no pixels"),
+                BlurError::InvalidRadius(radius) => {
+                    write!(f, "Radius {} is too large for the accumulators", radius)
+                }
+                BlurError::Overflow => write!(f, "Image size overflows usize"),
             }
+        }
+    }
 
-            let rw3 = self.current_row * self.w3;
-            let store_len = self.r_store.len();
+    impl std::error::Error for BlurError {}
 
-            self.r_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[rw3
-                        + 3 * (if self.current_index + rad < self.width {
-                            self.current_index + rad
-                        } else {
-                            self.w_minus_1
-                        })
-                        + 0] as usize
-                })
-                .sum::<usize>();
-            self.r_sum -= (&self.r_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
-
-            self.g_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[rw3
-                        + 3 * (if self.current_index + rad < self.width {
-                            self.current_index + rad
-                        } else {
-                            self.w_minus_1
-                        })
-                        + 1] as usize
-                })
-                .sum::<usize>();
-            self.g_sum -= (&self.g_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
-
-            self.b_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[rw3
-                        + 3 * (if self.current_index + rad < self.width {
-                            self.current_index + rad
-                        } else {
-                            self.w_minus_1
-                        })
-                        + 2] as usize
-                })
-                .sum::<usize>();
-            self.b_sum -= (&self.b_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
+    // Everything the blur functions would otherwise panic on
+    fn check_image<T: Sample>(
+        image: &ImageViewMut<T>,
+        options: &Options<T>,
+    ) -> Result<(), BlurError> {
+        if image.width == 0 || image.height == 0 || image.channels.count() == 0 {
+            return Err(BlurError::ZeroDimensions);
+        }
+        // Linear light and premultiplied alpha blur a copy in `T::Linear`
+        let premultiply = options.alpha == Alpha::Premultiply && image.channels.alpha().is_some();
+        let wide = options.gamma == Gamma::Linear || premultiply;
+        for radius in [options.radius_x, options.radius_y] {
+            if !fits_accumulators::<T>(radius) || wide && !fits_accumulators::<T::Linear>(radius) {
+                return Err(BlurError::InvalidRadius(radius));
+            }
+        }
 
-            let rw3_ci3 = rw3 + 3 * self.current_index;
+        let row = image
+            .width
+            .checked_mul(image.channels.count())
+            .ok_or(BlurError::Overflow)?;
+        if image.stride < row {
+            return Err(BlurError::Stride {
+                stride: image.stride,
+                row,
+            });
+        }
+        let expected = (image.height - 1)
+            .checked_mul(image.stride)
+            .and_then(|rows| rows.checked_add(row))
+            .ok_or(BlurError::Overflow)?;
+        if image.data.len() < expected {
+            return Err(BlurError::BufferSize {
+                expected,
+                actual: image.data.len(),
+            });
+        }
+        Ok(())
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum Channels {
+        RGBA,
+        RGB,
+        /// A single luminance channel
+        Gray,
+        /// Luminance followed by alpha
+        GrayAlpha,
+        /// Any number of channels, blurred independently of each other. Blurring
+        /// with no channels at all panics.
+        Interleaved(usize),
+    }
 
-            let r = self.data[rw3_ci3 + 0];
-            let g = self.data[rw3_ci3 + 1];
-            let b = self.data[rw3_ci3 + 2];
+    impl Channels {
+        /// Index of the alpha channel within a pixel, if there is one
+        pub fn alpha(&self) -> Option<usize> {
+            match self {
+                Channels::RGBA => Some(3),
+                Channels::GrayAlpha => Some(1),
+                Channels::RGB | Channels::Gray | Channels::Interleaved(_) => None,
+            }
+        }
 
-            self.r_store.push(r);
-            self.g_store.push(g);
-            self.b_store.push(b);
+        /// Number of interleaved samples per pixel
+        pub fn count(&self) -> usize {
+            match self {
+                Channels::RGBA => 4,
+                Channels::RGB => 3,
+                Channels::Gray => 1,
+                Channels::GrayAlpha => 2,
+                Channels::Interleaved(count) => *count,
+            }
+        }
+    }
 
-            self.current_index += 1;
+    /// A borrowed image whose rows may be padded, as handed out by decoders,
+    /// GPU readbacks or memory-mapped framebuffers.
+    ///
+    /// Row `y` starts at `data[y * stride]`. Only the first
+    /// `width * channels.count()` samples of a row are pixels; the padding after
+    /// them is never read.
+    #[derive(Clone, Copy, Debug)]
+    pub struct ImageView<'a, T = u8> {
+        pub data: &'a [T],
+        pub width: usize,
+        pub height: usize,
+        pub stride: usize,
+        pub channels: Channels,
+    }
 
-            Some([
-                ((self.r_sum * self.mul) >> self.shg) as u8,
-                ((self.g_sum * self.mul) >> self.shg) as u8,
-                ((self.b_sum * self.mul) >> self.shg) as u8,
-            ])
+    impl<'a, T> ImageView<'a, T> {
+        /// A view of tightly packed rows
+        pub fn new(data: &'a [T], width: usize, height: usize, channels: Channels) -> Self {
+            Self::with_stride(data, width, height, width * channels.count(), channels)
+        }
+
+        pub fn with_stride(
+            data: &'a [T],
+            width: usize,
+            height: usize,
+            stride: usize,
+            channels: Channels,
+        ) -> Self {
+            Self {
+                data,
+                width,
+                height,
+                stride,
+                channels,
+            }
+        }
+
+        fn row(&self, y: usize) -> &[T] {
+            let start = y * self.stride;
+            &self.data[start..start + self.width * self.channels.count()]
         }
     }
 
-    struct RGBVProcessor<'a> {
-        data: &'a [u8],
-        width: usize,
-        height: usize,
-        radius: usize,
-        current_index: usize,
-        current_col: usize,
-        r_store: Vec<u8>,
-        g_store: Vec<u8>,
-        b_store: Vec<u8>,
-        r_sum: usize,
-        g_sum: usize,
-        b_sum: usize,
-        w3: usize,
-        rr12: usize,
-        r_minus_1: usize,
-        h_minus_1: usize,
-        mul: usize,
-        shg: usize,
-    }
-
-    impl<'a> RGBVProcessor<'a> {
-        fn new(data: &'a [u8], width: usize, height: usize, radius: usize) -> Self {
+    /// Mutable counterpart of [`ImageView`]. Padding samples are left untouched.
+    #[derive(Debug)]
+    pub struct ImageViewMut<'a, T = u8> {
+        pub data: &'a mut [T],
+        pub width: usize,
+        pub height: usize,
+        pub stride: usize,
+        pub channels: Channels,
+    }
+
+    impl<'a, T> ImageViewMut<'a, T> {
+        /// A view of tightly packed rows
+        pub fn new(data: &'a mut [T], width: usize, height: usize, channels: Channels) -> Self {
+            let stride = width * channels.count();
+            Self::with_stride(data, width, height, stride, channels)
+        }
+
+        pub fn with_stride(
+            data: &'a mut [T],
+            width: usize,
+            height: usize,
+            stride: usize,
+            channels: Channels,
+        ) -> Self {
             Self {
                 data,
                 width,
                 height,
-                radius,
-                current_index: 0,
-                current_col: 0,
-                r_store: vec![data[0]; radius],
-                g_store: vec![data[1]; radius],
-                b_store: vec![data[2]; radius],
-                r_sum: (data[0] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[width * 3 * x + 0] as usize * (radius - 1 - x)
-                    }),
-                g_sum: (data[1] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[width * 3 * x + 1] as usize * (radius - 1 - x)
-                    }),
-                b_sum: (data[2] as usize * radius * (radius + 1) / 2)
-                    + (0..(radius - 1)).fold(0, |acc, x| {
-                        acc + data[width * 3 * x + 2] as usize * (radius - 1 - x)
-                    }),
-                w3: width * 3,
-                rr12: radius * (radius + 1) / 2,
-                r_minus_1: radius - 1,
-                h_minus_1: height - 1,
-                mul: MUL_TABLE[radius - 1],
-                shg: SHG_TABLE[radius - 1],
-            }
-        }
-    }
-
-    impl Iterator for RGBVProcessor<'_> {
-        type Item = [u8; 3];
-        fn next(&mut self) -> Option<Self::Item> {
-            if self.current_index == self.height {
-                self.current_col += 1;
-                if self.current_col == self.width {
-                    return None;
-                }
-                self.current_index = 0;
-                let c3 = self.current_col * 3;
-
-                self.r_store = vec![self.data[c3 + 0]; self.radius];
-                self.g_store = vec![self.data[c3 + 1]; self.radius];
-                self.b_store = vec![self.data[c3 + 2]; self.radius];
-
-                self.r_sum = (self.data[c3 + 0] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[c3 + self.w3 * x + 0] as usize * (self.r_minus_1 - x)
-                    });
-                self.g_sum = (self.data[c3 + 1] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[c3 + self.w3 * x + 1] as usize * (self.r_minus_1 - x)
-                    });
-                self.b_sum = (self.data[c3 + 2] as usize * self.rr12)
-                    + (0..(self.r_minus_1)).fold(0, |acc, x| {
-                        acc + self.data[c3 + self.w3 * x + 2] as usize * (self.r_minus_1 - x)
+                stride,
+                channels,
+            }
+        }
+
+        fn row(&self, y: usize) -> &[T] {
+            let start = y * self.stride;
+            &self.data[start..start + self.width * self.channels.count()]
+        }
+
+        fn row_mut(&mut self, y: usize) -> &mut [T] {
+            let start = y * self.stride;
+            &mut self.data[start..start + self.width * self.channels.count()]
+        }
+    }
+
+    /// A rectangle of pixels within an image
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Rect {
+        pub x: usize,
+        pub y: usize,
+        pub width: usize,
+        pub height: usize,
+    }
+
+    fn assert_same_shape<T>(src: &ImageView<T>, dst: &ImageViewMut<T>) {
+        assert!(
+            src.width == dst.width && src.height == dst.height && src.channels == dst.channels,
+            "Source and destination images differ in size or channels"
+        );
+    }
+
+    // Picks the processor for a number of channels
+    enum Processor<T: Sample> {
+        // Four channels of bytes, the layout the vector kernels handle
+        Vector(StackProcessor<u8, 4>, simd::Kernel),
+        One(StackProcessor<T, 1>),
+        Two(StackProcessor<T, 2>),
+        Three(StackProcessor<T, 3>),
+        Four(StackProcessor<T, 4>),
+        // One processor for every four channels, the last one taking the rest
+        Interleaved(Vec<Processor<T>>),
+    }
+
+    impl<T: Sample> Processor<T> {
+        fn new(channels: Channels, radius: usize, edge: Edge<T>, mask: u64) -> Self {
+            assert!(
+                fits_accumulators::<T>(radius),
+                "Radius too large for the accumulators"
+            );
+            Self::with_count(channels.count(), radius, edge, mask)
+        }
+
+        // Bit `c` of `mask` stands for channel `c` of the processor
+        fn with_count(count: usize, radius: usize, edge: Edge<T>, mask: u64) -> Self {
+            match count {
+                1 => Processor::One(StackProcessor::new(radius, edge, mask)),
+                2 => Processor::Two(StackProcessor::new(radius, edge, mask)),
+                3 => Processor::Three(StackProcessor::new(radius, edge, mask)),
+                4 if T::BYTES => {
+                    let edge = edge.map(|mut colour| {
+                        let bytes = T::bytes_mut(&mut colour).expect("BYTES samples are bytes");
+                        [bytes[0], bytes[1], bytes[2], bytes[3]]
                     });
+                    let processor = StackProcessor::new(radius, edge, mask);
+                    Processor::Vector(processor, simd::Kernel::detect())
+                }
+                4 => Processor::Four(StackProcessor::new(radius, edge, mask)),
+                // Every group sees the same constant, so it repeats every four channels
+                _ => Processor::Interleaved(
+                    (0..count)
+                        .step_by(4)
+                        .map(|first| {
+                            let count = (count - first).min(4);
+                            Self::with_count(count, radius, edge, shift_mask(mask, first))
+                        })
+                        .collect(),
+                ),
             }
+        }
 
-            let c3 = self.current_col * 3;
-            let store_len = self.r_store.len();
+        // Samples per pixel
+        fn count(&self) -> usize {
+            match self {
+                Processor::One(_) => 1,
+                Processor::Two(_) => 2,
+                Processor::Three(_) => 3,
+                Processor::Vector(..) | Processor::Four(_) => 4,
+                Processor::Interleaved(groups) => groups.iter().map(Processor::count).sum(),
+            }
+        }
 
-            self.r_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[c3
-                        + self.w3
-                            * (if self.current_index + rad < self.height {
-                                self.current_index + rad
-                            } else {
-                                self.h_minus_1
-                            })
-                        + 0] as usize
-                })
-                .sum::<usize>();
-            self.r_sum -= (&self.r_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
-
-            self.g_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[c3
-                        + self.w3
-                            * (if self.current_index + rad < self.height {
-                                self.current_index + rad
-                            } else {
-                                self.h_minus_1
-                            })
-                        + 1] as usize
+        // Blurs the `len` pixels starting at `start` and `step` samples apart, in place.
+        // Channels never mix, so a group of them can be blurred as a line of its own.
+        fn blur_line(&mut self, data: &mut [T], start: usize, step: usize, len: usize) {
+            match self {
+                Processor::Vector(p, kernel) => {
+                    let data = T::bytes_mut(data).expect("Vector processors only blur bytes");
+                    simd::blur_line(*kernel, p, data, start, step, len)
+                }
+                Processor::One(p) => p.blur_line(data, start, step, len),
+                Processor::Two(p) => p.blur_line(data, start, step, len),
+                Processor::Three(p) => p.blur_line(data, start, step, len),
+                Processor::Four(p) => p.blur_line(data, start, step, len),
+                Processor::Interleaved(groups) => {
+                    for (i, group) in groups.iter_mut().enumerate() {
+                        group.blur_line(data, start + 4 * i, step, len);
+                    }
+                }
+            }
+        }
+    }
+
+    // Whether `mask` blurs channel `c`, channels past 63 following bit 63
+    fn blurs_channel(mask: u64, c: usize) -> bool {
+        shift_mask(mask, c) & 1 == 1
+    }
+
+    // Bits of `mask` from channel `first` on. The shift is arithmetic, so bit 63
+    // carries on to the channels past it.
+    fn shift_mask(mask: u64, first: usize) -> u64 {
+        ((mask as i64) >> first.min(63)) as u64
+    }
+
+    // The sum of a line peaks at `T::MAX * radius^2`, when every sample is at its
+    // largest
+    fn fits_accumulators<T: Sample>(radius: usize) -> bool {
+        (radius as u64)
+            .checked_mul(radius as u64)
+            .and_then(|weight| weight.checked_mul(T::MAX))
+            .is_some_and(|sum| sum <= T::SUM_MAX)
+    }
+
+    // Turns the weighted sum of a window into its average. Radii covered by
+    // MUL_TABLE multiply and shift, larger ones and wider samples divide by the
+    // total weight.
+    #[derive(Clone, Copy, Debug)]
+    enum Scale {
+        Table { mul: usize, shg: usize },
+        Divide(usize),
+    }
+
+    impl Scale {
+        fn new<T: Sample>(r: usize) -> Self {
+            match (MUL_TABLE.get(r), SHG_TABLE.get(r)) {
+                (Some(&mul), Some(&shg)) if T::BYTES => Scale::Table { mul, shg },
+                _ => Scale::Divide((r + 1) * (r + 1)),
+            }
+        }
+    }
+
+    // Scalar stack blur of lines of `CC` interleaved channels
+    struct StackProcessor<T: Sample, const CC: usize> {
+        // The `2 * r + 1` pixels of the window, for lines longer than `r`
+        stack: Vec<[T; CC]>,
+        // The `r + 1` pixels past the end of the current line
+        after: Vec<[T; CC]>,
+        // Copy of the current line, for lines the window spans past both ends
+        line: Vec<[T; CC]>,
+        r: usize,
+        div: usize,
+        scale: Scale,
+        edge: Edge<T>,
+        // Channels written back, the others keep their samples
+        blurred: [bool; CC],
+    }
+
+    impl<T: Sample, const CC: usize> StackProcessor<T, CC> {
+        fn new(radius: usize, edge: Edge<T>, mask: u64) -> Self {
+            let r = radius - 1;
+            // The buffers are sized on the first line, so a radius far beyond
+            // the image doesn't allocate a stack for it
+            Self {
+                stack: vec![],
+                after: vec![],
+                line: vec![],
+                r,
+                div: 2 * r + 1,
+                scale: Scale::new::<T>(r),
+                edge,
+                blurred: std::array::from_fn(|c| blurs_channel(mask, c)),
+            }
+        }
+
+        // Fills the stack with the window around the first pixel of a line and
+        // `after` with the pixels past its end, as the edge mode extends it.
+        // Returns the initial sum, incoming sum and outgoing sum of every channel.
+        fn seed(&mut self, data: &[T], start: usize, step: usize, len: usize) -> [[T::Sum; CC]; 3] {
+            let r = self.r as isize;
+            let edge = self.edge;
+            let constant = match edge {
+                Edge::Constant(colour) => std::array::from_fn(|c| colour[c]),
+                _ => [T::default(); CC],
+            };
+            let at = |i: isize| -> [T; CC] {
+                edge.source(i, len).map_or(constant, |i| {
+                    let i = start + step * i;
+                    data[i..i + CC].try_into().unwrap()
                 })
-                .sum::<usize>();
-            self.g_sum -= (&self.g_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
-
-            self.b_sum += (0..self.radius)
-                .map(|rad| {
-                    self.data[c3
-                        + self.w3
-                            * (if self.current_index + rad < self.height {
-                                self.current_index + rad
-                            } else {
-                                self.h_minus_1
-                            })
-                        + 2] as usize
+            };
+
+            self.stack.resize(2 * self.r + 1, [T::default(); CC]);
+            self.after.resize(self.r + 1, [T::default(); CC]);
+            let mut sum = [T::Sum::default(); CC];
+            let mut sum_in = [T::Sum::default(); CC];
+            let mut sum_out = [T::Sum::default(); CC];
+            for i in -r..=r {
+                let p = at(i);
+                self.stack[(r + i) as usize] = p;
+                for c in 0..CC {
+                    sum[c] += p[c].weighted((r + 1 - i.abs()) as usize);
+                    if i > 0 {
+                        sum_in[c] += p[c].weighted(1);
+                    } else {
+                        sum_out[c] += p[c].weighted(1);
+                    }
+                }
+            }
+            for (k, p) in self.after.iter_mut().enumerate() {
+                *p = at((len + k) as isize);
+            }
+
+            [sum, sum_in, sum_out]
+        }
+
+        // Blurs the `len` pixels starting at `start` and `step` samples apart, in place.
+        fn blur_line(&mut self, data: &mut [T], start: usize, step: usize, len: usize) {
+            match self.scale {
+                Scale::Table { mul, shg } => {
+                    self.blur_line_as(data, start, step, len, |sum| T::mul_shr(sum, mul, shg))
+                }
+                Scale::Divide(weight) => {
+                    self.blur_line_as(data, start, step, len, |sum| T::divide(sum, weight))
+                }
+            }
+        }
+
+        // Line blur with the channels to write resolved up front, so lines with
+        // every channel blurred don't branch on it
+        #[inline(always)]
+        fn blur_line_as(
+            &mut self,
+            data: &mut [T],
+            start: usize,
+            step: usize,
+            len: usize,
+            average: impl Fn(T::Sum) -> T,
+        ) {
+            let blurred = self.blurred;
+            if blurred == [true; CC] {
+                self.blur_line_by(data, start, step, len, |_, sum, _| average(sum))
+            } else if blurred.contains(&true) {
+                self.blur_line_by(data, start, step, len, |c, sum, old| match blurred[c] {
+                    true => average(sum),
+                    false => old,
                 })
-                .sum::<usize>();
-            self.b_sum -= (&self.b_store[(store_len - self.radius)..])
-                .iter()
-                .map(|x| *x as usize)
-                .sum::<usize>();
+            }
+        }
+
+        // Line blur with the averaging of `Scale` resolved up front, so the inner
+        // loop doesn't branch on it. `store` gives the new sample of a channel from
+        // its sum and current sample. Pixels are only overwritten once they have
+        // been pushed onto the stack.
+        #[inline(always)]
+        fn blur_line_by(
+            &mut self,
+            data: &mut [T],
+            start: usize,
+            step: usize,
+            len: usize,
+            store: impl Fn(usize, T::Sum, T) -> T,
+        ) {
+            if self.r >= len {
+                return self.blur_short_line_by(data, start, step, len, store);
+            }
+            let pixel = |data: &[T], i: usize| -> [T; CC] {
+                let i = start + step * i;
+                data[i..i + CC].try_into().unwrap()
+            };
+
+            let [mut sum, mut sum_in, mut sum_out] = self.seed(data, start, step, len);
+            let mut stack_ptr = self.r;
+
+            for x in 0..len {
+                let i = start + step * x;
+                for c in 0..CC {
+                    data[i + c] = store(c, sum[c], data[i + c]);
+                    sum[c] -= sum_out[c];
+                }
 
-            let c3w3ci = c3 + self.w3 * self.current_index;
+                // The oldest entry leaves the window and the next pixel takes its slot
+                let stack_start = (stack_ptr + self.r + 1) % self.div;
+                let old = self.stack[stack_start];
+                let p = match x + self.r + 1 {
+                    next if next < len => pixel(data, next),
+                    next => self.after[next - len],
+                };
+                self.stack[stack_start] = p;
+                for c in 0..CC {
+                    sum_out[c] -= old[c].weighted(1);
+                    sum_in[c] += p[c].weighted(1);
+                    sum[c] += sum_in[c];
+                }
 
-            let r = self.data[c3w3ci + 0];
-            let g = self.data[c3w3ci + 1];
-            let b = self.data[c3w3ci + 2];
+                // The next pixel becomes the centre and moves to the outgoing half
+                stack_ptr = (stack_ptr + 1) % self.div;
+                let centre = self.stack[stack_ptr];
+                for c in 0..CC {
+                    sum_out[c] += centre[c].weighted(1);
+                    sum_in[c] -= centre[c].weighted(1);
+                }
+            }
+        }
 
-            self.r_store.push(r);
-            self.g_store.push(g);
-            self.b_store.push(b);
+        // `blur_line_by` for lines no longer than `r`, where the window reaches
+        // past both ends. Pixels are read from a copy of the line rather than the
+        // stack, and the ones past its ends are summed by how often the edge mode
+        // repeats each of them, so neither memory nor time grows with the radius.
+        #[inline(never)]
+        fn blur_short_line_by(
+            &mut self,
+            data: &mut [T],
+            start: usize,
+            step: usize,
+            len: usize,
+            store: impl Fn(usize, T::Sum, T) -> T,
+        ) {
+            self.line.clear();
+            self.line.extend((0..len).map(|x| {
+                let i = start + step * x;
+                <[T; CC]>::try_from(&data[i..i + CC]).unwrap()
+            }));
+            let (line, r, edge) = (&self.line, self.r as isize, self.edge);
+            let constant = match edge {
+                Edge::Constant(colour) => std::array::from_fn(|c| colour[c]),
+                _ => [T::default(); CC],
+            };
+            let at = |i: isize| edge.source(i, len).map_or(constant, |i| line[i]);
+
+            let mut sum = [T::Sum::default(); CC];
+            let mut sum_in = [T::Sum::default(); CC];
+            let mut sum_out = [T::Sum::default(); CC];
+            for (i, p) in line.iter().enumerate() {
+                for c in 0..CC {
+                    sum[c] += p[c].weighted(self.r + 1 - i);
+                    if i > 0 {
+                        sum_in[c] += p[c].weighted(1);
+                    } else {
+                        sum_out[c] += p[c].weighted(1);
+                    }
+                }
+            }
+            // Every position `i` past the ends weighs `r + 1 - |i|`
+            let outside = [(-r, -1, &mut sum_out), (len as isize, r, &mut sum_in)];
+            for (first, last, half) in outside {
+                edge.fold(first, last, len, |source, count, total| {
+                    let p = source.map_or(constant, |i| line[i]);
+                    let weight = count * (r as i128 + 1) - total.abs();
+                    for c in 0..CC {
+                        sum[c] += p[c].weighted(weight as usize);
+                        half[c] += p[c].weighted(count as usize);
+                    }
+                });
+            }
 
-            self.current_index += 1;
+            for x in 0..len {
+                let i = start + step * x;
+                for c in 0..CC {
+                    data[i + c] = store(c, sum[c], data[i + c]);
+                    sum[c] -= sum_out[c];
+                }
 
-            Some([
-                ((self.r_sum * self.mul) >> self.shg) as u8,
-                ((self.g_sum * self.mul) >> self.shg) as u8,
-                ((self.b_sum * self.mul) >> self.shg) as u8,
-            ])
+                let x = x as isize;
+                let (old, p, centre) = (at(x - r), at(x + r + 1), at(x + 1));
+                for c in 0..CC {
+                    sum_out[c] -= old[c].weighted(1);
+                    sum_in[c] += p[c].weighted(1);
+                    sum[c] += sum_in[c];
+                    sum_out[c] += centre[c].weighted(1);
+                    sum_in[c] -= centre[c].weighted(1);
+                }
+            }
         }
     }
 
@@ -741,6 +1300,59 @@ pub mod stackblur {
         channels: usize,
         r: u8,
     ) -> Vec<u8> {
+        let mut out = vec![0u8; width * height * channels];
+        let stride = width * channels;
+        unoptimized_blur_strided(
+            data,
+            stride,
+            &mut out,
+            stride,
+            width,
+            height,
+            channels,
+            r,
+            Edge::Clamp,
+        );
+        out
+    }
+
+    /// Same as [`unoptimized_blur`], but for images with padded rows.
+    pub fn unoptimized_blur_view(src: &ImageView, dst: &mut ImageViewMut, r: u8) {
+        unoptimized_blur_view_with(src, dst, r, Edge::Clamp);
+    }
+
+    /// Same as [`unoptimized_blur_view`], with pixels past the edges taken from `edge`.
+    pub fn unoptimized_blur_view_with(src: &ImageView, dst: &mut ImageViewMut, r: u8, edge: Edge) {
+        assert_same_shape(src, dst);
+        unoptimized_blur_strided(
+            src.data,
+            src.stride,
+            dst.data,
+            dst.stride,
+            src.width,
+            src.height,
+            src.channels.count(),
+            r,
+            edge,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn unoptimized_blur_strided(
+        data: &[u8],
+        src_stride: usize,
+        dst: &mut [u8],
+        dst_stride: usize,
+        width: usize,
+        height: usize,
+        channels: usize,
+        r: u8,
+        edge: Edge,
+    ) {
+        let constant = match edge {
+            Edge::Constant(colour) => colour,
+            _ => [0; 4],
+        };
         let r = r as usize;
         let radius = (r * 2) + 1;
         let mul = MUL_TABLE[r];
@@ -749,6 +1361,7 @@ pub mod stackblur {
         let mut hor_out = vec![0u8; width * height * channels];
 
         for row in 0..height {
+            let rs = row * src_stride;
             let rwc = row * width * channels;
 
             for i in 0..width {
@@ -756,29 +1369,12 @@ pub mod stackblur {
 
                 for channel in 0..channels {
                     let sum = (0..radius).fold(0usize, |acc, idx| {
-                        if r > i + idx {
-                            if idx < r {
-                                acc + data[rwc + ic + channel] as usize * (idx + 1)
-                            } else {
-                                acc + data[rwc + ic + channel] as usize * (radius - idx)
-                            }
-                        } else if i + idx - r < width {
-                            if idx < r {
-                                acc + data[rwc + (i + idx - r) * channels + channel] as usize
-                                    * (idx + 1)
-                            } else {
-                                acc + data[rwc + (i + idx - r) * channels + channel] as usize
-                                    * (radius - idx)
-                            }
-                        } else {
-                            if idx < r {
-                                acc + data[rwc + (width - 1) * channels + channel] as usize
-                                    * (idx + 1)
-                            } else {
-                                acc + data[rwc + (width - 1) * channels + channel] as usize
-                                    * (radius - idx)
-                            }
-                        }
+                        let weight = if idx < r { idx + 1 } else { radius - idx };
+                        let value = match edge.source((i + idx) as isize - r as isize, width) {
+                            Some(j) => data[rs + j * channels + channel],
+                            None => constant[channel % 4],
+                        };
+                        acc + value as usize * weight
                     });
 
                     hor_out[rwc + ic + channel] = ((sum * mul) >> shg) as u8;
@@ -786,51 +1382,24 @@ pub mod stackblur {
             }
         }
 
-        let mut ver_out = vec![0u8; width * height * channels];
-
         for col in 0..width {
             let cc = col * channels;
 
             for i in 0..height {
-                let iwc = i * width * channels;
-
                 for channel in 0..channels {
                     let sum = (0..radius).fold(0usize, |acc, idx| {
-                        if r > i + idx {
-                            if idx < r {
-                                acc + hor_out[cc + iwc + channel] as usize * (idx + 1)
-                            } else {
-                                acc + hor_out[cc + iwc + channel] as usize * (radius - idx)
-                            }
-                        } else if i + idx - r < height {
-                            if idx < r {
-                                acc + hor_out[cc + (i + idx - r) * width * channels + channel]
-                                    as usize
-                                    * (idx + 1)
-                            } else {
-                                acc + hor_out[cc + (i + idx - r) * width * channels + channel]
-                                    as usize
-                                    * (radius - idx)
-                            }
-                        } else {
-                            if idx < r {
-                                acc + hor_out[cc + (height - 1) * width * channels + channel]
-                                    as usize
-                                    * (idx + 1)
-                            } else {
-                                acc + hor_out[cc + (height - 1) * width * channels + channel]
-                                    as usize
-                                    * (radius - idx)
-                            }
-                        }
+                        let weight = if idx < r { idx + 1 } else { radius - idx };
+                        let value = match edge.source((i + idx) as isize - r as isize, height) {
+                            Some(j) => hor_out[cc + j * width * channels + channel],
+                            None => constant[channel % 4],
+                        };
+                        acc + value as usize * weight
                     });
 
-                    ver_out[cc + iwc + channel] = ((sum * mul) >> shg) as u8;
+                    dst[cc + i * dst_stride + channel] = ((sum * mul) >> shg) as u8;
                 }
             }
         }
-
-        ver_out
     }
 
     pub fn unoptimized_blur_2(
@@ -840,108 +1409,116 @@ pub mod stackblur {
         channels: usize,
         r: u8,
     ) -> Vec<u8> {
+        let mut out = vec![0u8; width * height * channels];
+        let stride = width * channels;
+        unoptimized_blur_2_strided(
+            data,
+            stride,
+            &mut out,
+            stride,
+            width,
+            height,
+            channels,
+            r,
+            Edge::Clamp,
+        );
+        out
+    }
+
+    /// Same as [`unoptimized_blur_2`], but for images with padded rows.
+    pub fn unoptimized_blur_2_view(src: &ImageView, dst: &mut ImageViewMut, r: u8) {
+        unoptimized_blur_2_view_with(src, dst, r, Edge::Clamp);
+    }
+
+    /// Same as [`unoptimized_blur_2_view`], with pixels past the edges taken from `edge`.
+    pub fn unoptimized_blur_2_view_with(
+        src: &ImageView,
+        dst: &mut ImageViewMut,
+        r: u8,
+        edge: Edge,
+    ) {
+        assert_same_shape(src, dst);
+        unoptimized_blur_2_strided(
+            src.data,
+            src.stride,
+            dst.data,
+            dst.stride,
+            src.width,
+            src.height,
+            src.channels.count(),
+            r,
+            edge,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn unoptimized_blur_2_strided(
+        data: &[u8],
+        src_stride: usize,
+        dst: &mut [u8],
+        dst_stride: usize,
+        width: usize,
+        height: usize,
+        channels: usize,
+        r: u8,
+        edge: Edge,
+    ) {
+        let constant = match edge {
+            Edge::Constant(colour) => colour,
+            _ => [0; 4],
+        };
         let r = r as usize;
         let radius = (r * 2) + 1;
         let mul = MUL_TABLE[r];
         let shg = SHG_TABLE[r];
 
         let hor_out_vec: Vec<u8> = (0..height)
-            .map(|row| {
-                let rwc = row * width * channels;
-
-                (0..width)
-                    .map(move |i| {
-                        let ic = i * channels;
-
-                        (0..channels).map(move |channel| {
-                            let sum = (0..radius).fold(0usize, |acc, idx| {
-                                if r > i + idx {
-                                    if idx < r {
-                                        acc + data[rwc + ic + channel] as usize * (idx + 1)
-                                    } else {
-                                        acc + data[rwc + ic + channel] as usize * (radius - idx)
-                                    }
-                                } else if i + idx - r < width {
-                                    if idx < r {
-                                        acc + data[rwc + (i + idx - r) * channels + channel]
-                                            as usize
-                                            * (idx + 1)
-                                    } else {
-                                        acc + data[rwc + (i + idx - r) * channels + channel]
-                                            as usize
-                                            * (radius - idx)
-                                    }
-                                } else {
-                                    if idx < r {
-                                        acc + data[rwc + (width - 1) * channels + channel] as usize
-                                            * (idx + 1)
-                                    } else {
-                                        acc + data[rwc + (width - 1) * channels + channel] as usize
-                                            * (radius - idx)
-                                    }
-                                }
-                            });
-
-                            ((sum * mul) >> shg) as u8
-                        })
+            .flat_map(|row| {
+                let rwc = row * src_stride;
+
+                (0..width).flat_map(move |i| {
+                    (0..channels).map(move |channel| {
+                        let sum = (0..radius).fold(0usize, |acc, idx| {
+                            let weight = if idx < r { idx + 1 } else { radius - idx };
+                            let value = match edge.source((i + idx) as isize - r as isize, width) {
+                                Some(j) => data[rwc + j * channels + channel],
+                                None => constant[channel % 4],
+                            };
+                            acc + value as usize * weight
+                        });
+
+                        ((sum * mul) >> shg) as u8
                     })
-                    .flatten()
+                })
             })
-            .flatten()
             .collect();
         let hor_out = &hor_out_vec[..];
 
-        let ver_out: Vec<u8> = (0..width)
-            .map(|col| {
-                let cc = col * channels;
-
-                (0..height)
-                    .map(move |i| {
-                        let iwc = i * width * channels;
-
-                        (0..channels).map(move |channel| {
-                            let sum = (0..radius).fold(0usize, |acc, idx| {
-                                if r > i + idx {
-                                    if idx < r {
-                                        acc + hor_out[cc + iwc + channel] as usize * (idx + 1)
-                                    } else {
-                                        acc + hor_out[cc + iwc + channel] as usize * (radius - idx)
-                                    }
-                                } else if i + idx - r < height {
-                                    if idx < r {
-                                        acc + hor_out
-                                            [cc + (i + idx - r) * width * channels + channel]
-                                            as usize
-                                            * (idx + 1)
-                                    } else {
-                                        acc + hor_out
-                                            [cc + (i + idx - r) * width * channels + channel]
-                                            as usize
-                                            * (radius - idx)
-                                    }
-                                } else {
-                                    if idx < r {
-                                        acc + hor_out
-                                            [cc + (height - 1) * width * channels + channel]
-                                            as usize
-                                            * (idx + 1)
-                                    } else {
-                                        acc + hor_out
-                                            [cc + (height - 1) * width * channels + channel]
-                                            as usize
-                                            * (radius - idx)
-                                    }
-                                }
-                            });
-                            ((sum * mul) >> shg) as u8
-                        })
+        dst.chunks_mut(dst_stride)
+            .take(height)
+            .enumerate()
+            .for_each(|(i, row)| {
+                let ver_out = (0..width).flat_map(move |col| {
+                    let cc = col * channels;
+
+                    (0..channels).map(move |channel| {
+                        let sum = (0..radius).fold(0usize, |acc, idx| {
+                            let weight = if idx < r { idx + 1 } else { radius - idx };
+                            let value = match edge.source((i + idx) as isize - r as isize, height) {
+                                Some(j) => hor_out[cc + j * width * channels + channel],
+                                None => constant[channel % 4],
+                            };
+                            acc + value as usize * weight
+                        });
+                        ((sum * mul) >> shg) as u8
                     })
-                    .flatten()
-            })
-            .flatten()
-            .collect();
+                });
 
-        ver_out
+                row[..width * channels]
+                    .iter_mut()
+                    .zip(ver_out)
+                    .for_each(|(out, value)| *out = value);
+            });
     }
 
     #[rustfmt::skip]
@@ -981,4 +1558,881 @@ pub mod stackblur {
         24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24,
         24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24,
         24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24, 24 ];
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn noise(len: usize) -> Vec<u8> {
+            let mut state = 0x9e37_79b9u32;
+            (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as u8
+                })
+                .collect()
+        }
+
+        fn widen<T: Copy + Into<f64>>(data: &[T]) -> Vec<f64> {
+            data.iter().map(|&v| v.into()).collect()
+        }
+
+        // Weighted average of every window computed directly, one pass at a
+        // time, with edge pixels repeated past the ends of each line. Each pass
+        // rounds its averages with `round`.
+        fn reference<T: Copy + Into<f64>>(
+            data: &[T],
+            width: usize,
+            height: usize,
+            cc: usize,
+            radius: usize,
+            round: fn(f64) -> f64,
+        ) -> Vec<f64> {
+            let r = radius as isize - 1;
+            let weight = (radius * radius) as f64;
+            let pass = |src: &[f64], len: usize, at: &dyn Fn(usize, usize) -> usize| {
+                let mut dst = src.to_vec();
+                for line in 0..src.len() / cc / len {
+                    for x in 0..len {
+                        for c in 0..cc {
+                            let sum: f64 = (-r..=r)
+                                .map(|k| {
+                                    let i = (x as isize + k).clamp(0, len as isize - 1);
+                                    src[at(line, i as usize) + c] * (r + 1 - k.abs()) as f64
+                                })
+                                .sum();
+                            dst[at(line, x) + c] = round(sum / weight);
+                        }
+                    }
+                }
+                dst
+            };
+            let data = widen(data);
+            let horizontal = pass(&data, width, &|y, x| (y * width + x) * cc);
+            pass(&horizontal, height, &|x, y| (y * width + x) * cc)
+        }
+
+        #[test]
+        fn large_radii_match_reference() {
+            let (width, height) = (23, 17);
+            for channels in [
+                Channels::RGBA,
+                Channels::RGB,
+                Channels::Gray,
+                Channels::GrayAlpha,
+            ] {
+                let cc = channels.count();
+                let data = noise(width * height * cc);
+                for radius in [257, 1000, 5000] {
+                    assert!(
+                        widen(&blur(&data, width, height, &channels, radius))
+                            == reference(&data, width, height, cc, radius, f64::floor),
+                        "{:?} differs at radius {}",
+                        channels,
+                        radius
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn large_radii_keep_saturated_input() {
+            let (width, height) = (3000, 2);
+            let data = vec![255u8; width * height * 4];
+            assert!(blur(&data, width, height, &Channels::RGBA, 5000) == data);
+        }
+
+        #[test]
+        fn u16_samples_match_reference() {
+            let (width, height) = (23, 17);
+            for channels in [
+                Channels::RGBA,
+                Channels::RGB,
+                Channels::Gray,
+                Channels::Interleaved(5),
+            ] {
+                let cc = channels.count();
+                let data: Vec<u16> = noise(width * height * cc * 2)
+                    .chunks(2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                    .collect();
+                for radius in [1, 2, 7, 100, 300, 1000] {
+                    assert!(
+                        widen(&blur(&data, width, height, &channels, radius))
+                            == reference(&data, width, height, cc, radius, f64::floor),
+                        "{:?} differs at radius {}",
+                        channels,
+                        radius
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn u16_large_radii_keep_saturated_input() {
+            let (width, height) = (3000, 2);
+            let data = vec![u16::MAX; width * height * 4];
+            assert!(blur(&data, width, height, &Channels::RGBA, 5000) == data);
+        }
+
+        #[test]
+        fn f32_samples_match_reference() {
+            let (width, height) = (23, 17);
+            for channels in [
+                Channels::RGBA,
+                Channels::RGB,
+                Channels::GrayAlpha,
+                Channels::Interleaved(6),
+            ] {
+                let cc = channels.count();
+                // HDR values, well past 1.0
+                let data: Vec<f32> = noise(width * height * cc)
+                    .iter()
+                    .map(|&v| v as f32 / 16.0)
+                    .collect();
+                for radius in [1, 2, 7, 300, 1000] {
+                    let expected = reference(&data, width, height, cc, radius, |v| v);
+                    let actual = widen(&blur(&data, width, height, &channels, radius));
+                    for (a, e) in actual.iter().zip(&expected) {
+                        assert!(
+                            (a - e).abs() <= 1e-5 * e.max(1.0),
+                            "{:?} at radius {}: {} instead of {}",
+                            channels,
+                            radius,
+                            a,
+                            e
+                        );
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn linear_light_matches_reference() {
+            let (width, height) = (23, 17);
+            fn linear<T>(radius: usize) -> Options<T> {
+                Options {
+                    gamma: Gamma::Linear,
+                    ..Options::new(radius)
+                }
+            }
+            for channels in [Channels::RGBA, Channels::Gray, Channels::GrayAlpha] {
+                let cc = channels.count();
+                let alpha = channels.alpha();
+                let data = noise(width * height * cc);
+                let decoded: Vec<f64> = data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &v)| match Some(i % cc) == alpha {
+                        true => v as f64 / 255.0,
+                        false => srgb::decode(v as f64 / 255.0),
+                    })
+                    .collect();
+                for radius in [2, 9, 300] {
+                    let expected = reference(&decoded, width, height, cc, radius, |v| v);
+                    let expected =
+                        expected
+                            .iter()
+                            .enumerate()
+                            .map(|(i, &v)| match Some(i % cc) == alpha {
+                                true => v,
+                                false => srgb::encode(v),
+                            });
+
+                    let mut bytes = data.clone();
+                    let image = &mut ImageViewMut::new(&mut bytes, width, height, channels);
+                    blur_view_in_place_with(image, &linear(radius));
+                    let mut words: Vec<u16> = data.iter().map(|&v| v as u16 * 257).collect();
+                    let image = &mut ImageViewMut::new(&mut words, width, height, channels);
+                    blur_view_in_place_with(image, &linear(radius));
+                    let mut floats: Vec<f32> = data.iter().map(|&v| v as f32 / 255.0).collect();
+                    let image = &mut ImageViewMut::new(&mut floats, width, height, channels);
+                    blur_view_in_place_with(image, &linear(radius));
+
+                    for (i, e) in expected.enumerate() {
+                        let close = (bytes[i] as f64 - e * 255.0).abs() <= 1.0
+                            && (words[i] as f64 - e * 65535.0).abs() <= 1.0
+                            && (floats[i] as f64 - e).abs() <= 1e-5;
+                        assert!(
+                            close,
+                            "{:?} at radius {}: {}, {} and {} instead of {}",
+                            channels, radius, bytes[i], words[i], floats[i], e
+                        );
+                    }
+                }
+            }
+
+            // Black and white stripes average to half the light, not half the code
+            let mut stripes: Vec<u8> = (0..64).map(|x| if x % 2 == 0 { 0 } else { 255 }).collect();
+            let image = &mut ImageViewMut::new(&mut stripes, 64, 1, Channels::Gray);
+            blur_view_in_place_with(image, &linear(11));
+            assert!(stripes[20..44].iter().all(|&v| (186..=189).contains(&v)));
+        }
+
+        #[test]
+        fn edges_match_unoptimized_reference() {
+            // Positions -4..7 of the line `a b c`
+            let sources = |edge: Edge| (-4..7).map(|i| edge.source(i, 3)).collect::<Vec<_>>();
+            let (a, b, c) = (Some(0), Some(1), Some(2));
+            assert_eq!(sources(Edge::Clamp), [a, a, a, a, a, b, c, c, c, c, c]);
+            assert_eq!(sources(Edge::Mirror), [c, c, b, a, a, b, c, c, b, a, a]);
+            assert_eq!(sources(Edge::Wrap), [c, a, b, c, a, b, c, a, b, c, a]);
+            let none = None;
+            let constant = sources(Edge::Constant([0; 4]));
+            assert_eq!(
+                constant,
+                [none, none, none, none, a, b, c, none, none, none, none]
+            );
+
+            let edges = [
+                Edge::Clamp,
+                Edge::Mirror,
+                Edge::Wrap,
+                Edge::Constant([0, 255, 40, 128]),
+            ];
+            for ((width, height), channels) in [(9, 6), (31, 23)].into_iter().flat_map(|size| {
+                [
+                    Channels::RGBA,
+                    Channels::RGB,
+                    Channels::Gray,
+                    Channels::Interleaved(6),
+                ]
+                .map(|channels| (size, channels))
+            }) {
+                let data = noise(width * height * channels.count());
+                let src = ImageView::new(&data, width, height, channels);
+                for (radius, edge) in [2, 5, 9, 10, 40]
+                    .into_iter()
+                    .flat_map(|r| edges.map(|e| (r, e)))
+                {
+                    let mut expected = vec![0; data.len()];
+                    let dst = &mut ImageViewMut::new(&mut expected, width, height, channels);
+                    unoptimized_blur_view_with(&src, dst, radius as u8 - 1, edge);
+                    let mut expected_2 = vec![0; data.len()];
+                    let dst = &mut ImageViewMut::new(&mut expected_2, width, height, channels);
+                    unoptimized_blur_2_view_with(&src, dst, radius as u8 - 1, edge);
+                    assert!(expected == expected_2);
+
+                    let options = Options {
+                        edge,
+                        ..Options::new(radius)
+                    };
+                    let mut actual = data.clone();
+                    let image = &mut ImageViewMut::new(&mut actual, width, height, channels);
+                    blur_view_in_place_with(image, &options);
+                    assert!(
+                        actual == expected,
+                        "{:?} of {}x{} differs at radius {} with {:?}",
+                        channels,
+                        width,
+                        height,
+                        radius,
+                        edge
+                    );
+
+                    // A region at the corner of the image sees the same edges
+                    let mut region = data.clone();
+                    let image = &mut ImageViewMut::new(&mut region, width, height, channels);
+                    let rect = Rect {
+                        x: 0,
+                        y: 0,
+                        width: 4,
+                        height: 3,
+                    };
+                    blur_region_with(image, &rect, &options);
+                    let cc = channels.count();
+                    for y in 0..3 {
+                        let row = y * width * cc..(y * width + 4) * cc;
+                        assert!(region[row.clone()] == expected[row]);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn mask_picks_radius_per_pixel() {
+            let (width, height) = (40, 30);
+            let data = noise(width * height * 3);
+            let blurred = |radius| blur(&data, width, height, &Channels::RGB, radius);
+            let masked = |mask: &[u8], radius| {
+                let mut out = data.clone();
+                let mask = ImageView::new(mask, width, height, Channels::Gray);
+                let image = &mut ImageViewMut::new(&mut out, width, height, Channels::RGB);
+                blur_masked(image, &mask, radius);
+                out
+            };
+
+            // Left half sharp, right half fully blurred
+            let halves: Vec<u8> = (0..width * height)
+                .map(|i| if i % width < width / 2 { 0 } else { 255 })
+                .collect();
+            let out = masked(&halves, 51);
+            let full = blurred(51);
+            for (i, pixel) in out.chunks(3).enumerate() {
+                let expected = if i % width < width / 2 { &data } else { &full };
+                assert_eq!(pixel, &expected[i * 3..i * 3 + 3], "pixel {}", i);
+            }
+
+            // 40 of 255 is radius 8 of 51, one of the radii blurred with
+            assert!(mask_radii(51).contains(&8));
+            assert_eq!(masked(&vec![40; width * height], 51), blurred(8));
+
+            // In between radii fall between their neighbours
+            let out = masked(&vec![35; width * height], 51);
+            let (low, high) = (blurred(6), blurred(8));
+            for ((&o, &l), &h) in out.iter().zip(&low).zip(&high) {
+                assert!(l.min(h) <= o && o <= l.max(h));
+            }
+        }
+
+        #[test]
+        fn channel_mask_leaves_other_channels_untouched() {
+            let (width, height) = (33, 21);
+            for (channels, mask) in [
+                (Channels::RGBA, 0b1000),
+                (Channels::RGBA, 0b0110),
+                (Channels::RGB, 0b001),
+                (Channels::GrayAlpha, 0),
+                (Channels::Interleaved(7), 0b101_0101),
+                (Channels::Interleaved(9), !0b1111),
+            ] {
+                let cc = channels.count();
+                let data = noise(width * height * cc);
+                for radius in [4, 300] {
+                    for (alpha, gamma) in [
+                        (Alpha::Independent, Gamma::Encoded),
+                        (Alpha::Premultiply, Gamma::Linear),
+                    ] {
+                        let options = Options {
+                            alpha,
+                            gamma,
+                            ..Options::new(radius)
+                        };
+                        let blurred = |channel_mask| {
+                            let mut out = data.clone();
+                            let image = &mut ImageViewMut::new(&mut out, width, height, channels);
+                            let options = Options {
+                                channel_mask,
+                                ..options
+                            };
+                            blur_view_in_place_with(image, &options);
+                            out
+                        };
+                        let (all, some) = (blurred(u64::MAX), blurred(mask));
+                        for (i, &sample) in some.iter().enumerate() {
+                            // Premultiplying mixes alpha into the other channels
+                            let c = i % cc;
+                            let expected = match blurs_channel(mask, c) {
+                                true if alpha == Alpha::Premultiply => continue,
+                                true => all[i],
+                                false => data[i],
+                            };
+                            assert_eq!(
+                                sample, expected,
+                                "channel {} of {:?} with mask {:b} at radius {}",
+                                c, channels, mask, radius
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn layouts_match_rgba_channels() {
+            let (width, height) = (45, 38);
+            let rgba = noise(width * height * 4);
+            for radius in [1, 2, 7, 100, 300] {
+                let expected = blur(&rgba, width, height, &Channels::RGBA, radius);
+                for channels in [Channels::RGB, Channels::Gray, Channels::GrayAlpha] {
+                    let cc = channels.count();
+                    let leading = |data: &[u8]| -> Vec<u8> {
+                        data.chunks(4).flat_map(|p| p[..cc].to_vec()).collect()
+                    };
+                    assert!(
+                        blur(&leading(&rgba), width, height, &channels, radius)
+                            == leading(&expected),
+                        "{:?} differs at radius {}",
+                        channels,
+                        radius
+                    );
+                }
+            }
+        }
+
+        #[test]
+        fn interleaved_channels_match_gray() {
+            let (width, height) = (37, 29);
+            for count in [1, 4, 5, 7, 16] {
+                let data = noise(width * height * count);
+                for radius in [3, 40] {
+                    let blurred = blur(&data, width, height, &Channels::Interleaved(count), radius);
+                    for c in 0..count {
+                        let channel = |data: &[u8]| -> Vec<u8> {
+                            data.iter().skip(c).step_by(count).copied().collect()
+                        };
+                        assert!(
+                            blur(&channel(&data), width, height, &Channels::Gray, radius)
+                                == channel(&blurred),
+                            "channel {} of {} differs at radius {}",
+                            c,
+                            count,
+                            radius
+                        );
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn premultiplied_alpha_keeps_colour_of_opaque_pixels() {
+            let (width, height) = (24, 20);
+            // Opaque red square on transparent black
+            let mut rgba = vec![0u8; width * height * 4];
+            for y in 6..14 {
+                for x in 8..16 {
+                    rgba[(y * width + x) * 4..][..4].copy_from_slice(&[255, 0, 0, 255]);
+                }
+            }
+            let blurred = |data: &[u8], alpha: Alpha| {
+                let mut data = data.to_vec();
+                let mut image = ImageViewMut::new(&mut data, width, height, Channels::RGBA);
+                blur_view_in_place_with(
+                    &mut image,
+                    &Options {
+                        alpha,
+                        ..Options::new(5)
+                    },
+                );
+                data
+            };
+            let independent = blurred(&rgba, Alpha::Independent);
+            let premultiplied = blurred(&rgba, Alpha::Premultiply);
+            assert!(independent.chunks(4).any(|p| p[3] > 0 && p[0] < 255));
+            for (i, p) in premultiplied.chunks(4).enumerate() {
+                assert_eq!(p[3], independent[i * 4 + 3]);
+                let colour = if p[3] > 0 { [255, 0, 0] } else { [0; 3] };
+                assert_eq!(p[..3], colour, "pixel {} is {:?}", i, p);
+            }
+            assert_eq!(blurred(&rgba, Alpha::Premultiplied), independent);
+
+            // Same for white on transparent black in gray and alpha floats
+            let mut gray_alpha = vec![0f32; width * height * 2];
+            for pixel in gray_alpha.chunks_mut(2).skip(50).take(120) {
+                pixel.copy_from_slice(&[1.0, 1.0]);
+            }
+            let mut image = ImageViewMut::new(&mut gray_alpha, width, height, Channels::GrayAlpha);
+            blur_view_in_place_with(
+                &mut image,
+                &Options {
+                    alpha: Alpha::Premultiply,
+                    ..Options::new(4)
+                },
+            );
+            for p in gray_alpha.chunks(2) {
+                assert_eq!(p[0], if p[1] > 0.0 { 1.0 } else { 0.0 }, "{:?}", p);
+            }
+        }
+
+        #[test]
+        fn premultiplied_alpha_keeps_colour_of_flat_translucent_images() {
+            for alpha in [1, 2, 10, 128] {
+                let pixel: [u8; 4] = [200, 100, 50, alpha];
+                let rgba = pixel.repeat(16 * 12);
+                let mut data = rgba.clone();
+                let mut image = ImageViewMut::new(&mut data, 16, 12, Channels::RGBA);
+                blur_view_in_place_with(
+                    &mut image,
+                    &Options {
+                        alpha: Alpha::Premultiply,
+                        ..Options::new(5)
+                    },
+                );
+                assert_eq!(data, rgba, "alpha {}", alpha);
+
+                let rgba: Vec<u16> = rgba.iter().map(|&s| s as u16 * 257).collect();
+                let mut data = rgba.clone();
+                let mut image = ImageViewMut::new(&mut data, 16, 12, Channels::RGBA);
+                blur_view_in_place_with(
+                    &mut image,
+                    &Options {
+                        alpha: Alpha::Premultiply,
+                        ..Options::new(5)
+                    },
+                );
+                assert_eq!(data, rgba, "alpha {}", alpha);
+            }
+        }
+
+        #[test]
+        fn radii_past_the_image_allocate_for_the_image() {
+            // A flat image stays flat however far the window reaches, and the
+            // stack would take gigabytes
+            let pixel = [0.25f32, 0.5, 0.75, 1.0];
+            let rgba = pixel.repeat(3 * 2);
+            assert_eq!(try_blur(&rgba, 3, 2, &Channels::RGBA, 1 << 31), Ok(rgba));
+            let rgba = [9u8, 80, 160, 255].repeat(2);
+            assert_eq!(try_blur(&rgba, 1, 2, &Channels::RGBA, 1 << 28), Ok(rgba));
+
+            for edge in [Edge::Mirror, Edge::Wrap, Edge::Constant([0.0; 4])] {
+                let mut data = pixel.to_vec();
+                let mut image = ImageViewMut::new(&mut data, 1, 1, Channels::RGBA);
+                let options = Options {
+                    edge,
+                    ..Options::new(1 << 30)
+                };
+                blur_view_in_place_with(&mut image, &options);
+                let expected = match edge {
+                    Edge::Constant(_) => [0.0; 4],
+                    _ => pixel,
+                };
+                for (c, (sample, expected)) in data.iter().zip(expected).enumerate() {
+                    assert!((sample - expected).abs() < 1e-6, "{:?} {}", edge, c);
+                }
+            }
+        }
+
+        #[test]
+        fn region_matches_full_blur_inside_and_keeps_the_rest() {
+            let (width, height) = (40, 30);
+            let data = noise(width * height * 4);
+            let inside = |rect: &Rect, x: usize, y: usize| {
+                (rect.x..rect.x + rect.width).contains(&x)
+                    && (rect.y..rect.y + rect.height).contains(&y)
+            };
+            let interior = Rect {
+                x: 12,
+                y: 9,
+                width: 15,
+                height: 8,
+            };
+            let corner = Rect {
+                x: 0,
+                y: 22,
+                width: 10,
+                height: 8,
+            };
+            let edges = [
+                Edge::Clamp,
+                Edge::Mirror,
+                Edge::Wrap,
+                Edge::Constant([0, 255, 40, 128]),
+            ];
+            for (rect, edge) in [interior, corner]
+                .into_iter()
+                .flat_map(|r| edges.map(|e| (r, e)))
+            {
+                let options = Options {
+                    radius_y: 4,
+                    edge,
+                    ..Options::new(6)
+                };
+                let mut full = data.clone();
+                let image = &mut ImageViewMut::new(&mut full, width, height, Channels::RGBA);
+                blur_view_in_place_with(image, &options);
+                let mut region = data.clone();
+                let image = &mut ImageViewMut::new(&mut region, width, height, Channels::RGBA);
+                blur_region_with(image, &rect, &options);
+
+                for (i, pixel) in region.chunks(4).enumerate() {
+                    let (x, y) = (i % width, i / width);
+                    let expected = match inside(&rect, x, y) {
+                        true => &full[i * 4..][..4],
+                        false => &data[i * 4..][..4],
+                    };
+                    assert_eq!(
+                        pixel, expected,
+                        "{:?} with {:?} at {}, {}",
+                        rect, edge, x, y
+                    );
+                }
+            }
+
+            let mut region = data.clone();
+            let image = &mut ImageViewMut::new(&mut region, width, height, Channels::RGBA);
+            blur_region(image, &interior, 6);
+            let full = blur(&data, width, height, &Channels::RGBA, 6);
+            for (i, pixel) in region.chunks(4).enumerate() {
+                let source = match inside(&interior, i % width, i / width) {
+                    true => &full,
+                    false => &data,
+                };
+                assert_eq!(pixel, &source[i * 4..][..4]);
+            }
+        }
+
+        #[test]
+        fn regions_reaching_past_the_image_are_clipped() {
+            let (width, height) = (7, 5);
+            let data = noise(width * height * 3);
+            let mut region = data.clone();
+            let mut image = ImageViewMut::new(&mut region, width, height, Channels::RGB);
+            let rect = Rect {
+                x: 1,
+                y: 2,
+                width: usize::MAX,
+                height: usize::MAX,
+            };
+            blur_region_with(&mut image, &rect, &Options::new(3));
+            let mut rest = data.clone();
+            let mut image = ImageViewMut::new(&mut rest, width, height, Channels::RGB);
+            let rect = Rect {
+                x: 1,
+                y: 2,
+                width: width - 1,
+                height: height - 2,
+            };
+            blur_region_with(&mut image, &rect, &Options::new(3));
+            assert_eq!(region, rest);
+        }
+
+        #[test]
+        fn passes_take_their_own_radius() {
+            let (width, height) = (13, 9);
+            let data = noise(width * height * 4);
+            let with = |data: &[u8], radius_x: usize, radius_y: usize| {
+                let mut data = data.to_vec();
+                let mut image = ImageViewMut::new(&mut data, width, height, Channels::RGBA);
+                let options = Options {
+                    radius_x,
+                    radius_y,
+                    ..Options::new(0)
+                };
+                blur_view_in_place_with(&mut image, &options);
+                data
+            };
+
+            // A line of one pixel blurs to itself, so a single pass blurs every
+            // row or column like an image of its own
+            let rows = with(&data, 5, 0);
+            for (row, blurred) in data.chunks(width * 4).zip(rows.chunks(width * 4)) {
+                assert_eq!(blurred, blur(row, width, 1, &Channels::RGBA, 5));
+            }
+            let columns = with(&data, 0, 5);
+            for x in 0..width {
+                let column = |data: &[u8]| -> Vec<u8> {
+                    let pixels = data.chunks(4).skip(x).step_by(width);
+                    pixels.flatten().copied().collect()
+                };
+                let expected = blur(&column(&data), 1, height, &Channels::RGBA, 5);
+                assert_eq!(column(&columns), expected);
+            }
+
+            assert_eq!(with(&data, 0, 0), data);
+            assert_eq!(with(&data, 3, 7), with(&with(&data, 3, 0), 0, 7));
+            assert_ne!(with(&data, 3, 7), with(&data, 7, 3));
+        }
+
+        #[cfg(feature = "rayon")]
+        #[test]
+        fn parallel_blur_matches_blur() {
+            // Wider than two strips, so the vertical pass splits across tasks
+            let (width, height) = (70, 23);
+            let edges = [
+                Edge::Clamp,
+                Edge::Mirror,
+                Edge::Wrap,
+                Edge::Constant([0, 255, 40, 128]),
+            ];
+            for channels in [
+                Channels::RGBA,
+                Channels::RGB,
+                Channels::GrayAlpha,
+                Channels::Gray,
+                Channels::Interleaved(6),
+            ] {
+                let data = noise(width * height * channels.count());
+                let expected = blur(&data, width, height, &channels, 6);
+                for threads in [0, 1, 4] {
+                    let blurred = blur_parallel(&data, width, height, &channels, 6, threads);
+                    assert!(blurred == expected, "{:?} on {} threads", channels, threads);
+
+                    for edge in edges {
+                        let with = |threads: usize| {
+                            let mut data = data.clone();
+                            let mut image = ImageViewMut::new(&mut data, width, height, channels);
+                            let options = Options {
+                                radius_x: 9,
+                                edge,
+                                threads,
+                                ..Options::new(4)
+                            };
+                            blur_view_in_place_with(&mut image, &options);
+                            data
+                        };
+                        assert!(
+                            with(threads) == with(1),
+                            "{:?} with {:?} on {} threads",
+                            channels,
+                            edge,
+                            threads
+                        );
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn padded_rows_match_packed_rows() {
+            let (width, height, channels) = (11, 7, Channels::RGB);
+            let row = width * 3;
+            let packed = noise(width * height * 3);
+            // Padding of the source is noise the blur mustn't read, padding of the
+            // destination a pattern it mustn't write
+            let pad = |data: &[u8], stride: usize, padding: &mut dyn FnMut() -> u8| {
+                let mut padded = vec![];
+                for row in data.chunks(row) {
+                    padded.extend_from_slice(row);
+                    padded.extend((row.len()..stride).map(|_| padding()));
+                }
+                padded
+            };
+            let mut padding = noise(height * 4).into_iter();
+            let src = pad(&packed, row + 4, &mut || padding.next().unwrap());
+            let src = ImageView::with_stride(&src, width, height, row + 4, channels);
+            let dst_stride = row + 9;
+            let unpad = |padded: &[u8]| -> Vec<u8> {
+                let rows = padded.chunks(dst_stride);
+                assert!(rows.clone().all(|row| row[width * 3..] == [0xa5; 9]));
+                rows.flat_map(|row| &row[..width * 3]).copied().collect()
+            };
+
+            let mut dst = pad(&vec![0; packed.len()], dst_stride, &mut || 0xa5);
+            let image =
+                &mut ImageViewMut::with_stride(&mut dst, width, height, dst_stride, channels);
+            blur_view(&src, image, 5);
+            assert_eq!(unpad(&dst), blur(&packed, width, height, &channels, 5));
+
+            let mut dst = pad(&packed, dst_stride, &mut || 0xa5);
+            let image =
+                &mut ImageViewMut::with_stride(&mut dst, width, height, dst_stride, channels);
+            blur_view_in_place(image, 5);
+            assert_eq!(unpad(&dst), blur(&packed, width, height, &channels, 5));
+
+            let mut dst = pad(&vec![0; packed.len()], dst_stride, &mut || 0xa5);
+            let image =
+                &mut ImageViewMut::with_stride(&mut dst, width, height, dst_stride, channels);
+            unoptimized_blur_view(&src, image, 4);
+            assert_eq!(unpad(&dst), unoptimized_blur(&packed, width, height, 3, 4));
+
+            let mut dst = pad(&vec![0; packed.len()], dst_stride, &mut || 0xa5);
+            let image =
+                &mut ImageViewMut::with_stride(&mut dst, width, height, dst_stride, channels);
+            unoptimized_blur_2_view(&src, image, 4);
+            assert_eq!(
+                unpad(&dst),
+                unoptimized_blur_2(&packed, width, height, 3, 4)
+            );
+        }
+
+        #[test]
+        fn try_blur_matches_blur() {
+            let data = noise(9 * 7 * 3);
+            assert_eq!(
+                try_blur(&data, 9, 7, &Channels::RGB, 4),
+                Ok(blur(&data, 9, 7, &Channels::RGB, 4))
+            );
+        }
+
+        #[test]
+        fn try_blur_rejects_malformed_input() {
+            let rgba = Channels::RGBA;
+            assert_eq!(
+                try_blur(&[0u8; 15], 2, 2, &rgba, 3),
+                Err(BlurError::BufferSize {
+                    expected: 16,
+                    actual: 15
+                })
+            );
+            assert_eq!(
+                try_blur::<u8>(&[], 0, 4, &rgba, 3),
+                Err(BlurError::ZeroDimensions)
+            );
+            assert_eq!(
+                try_blur(&[0u8; 4], 1, 1, &rgba, usize::MAX / 2),
+                Err(BlurError::InvalidRadius(usize::MAX / 2))
+            );
+            // Fits the sums of bytes, but not those of the 16-bit copy
+            let mut pixel = [0u8; 4];
+            let options = Options {
+                alpha: Alpha::Premultiply,
+                ..Options::new(20_000_000)
+            };
+            assert_eq!(
+                try_blur_view_in_place_with(
+                    &mut ImageViewMut::new(&mut pixel, 1, 1, rgba),
+                    &options
+                ),
+                Err(BlurError::InvalidRadius(20_000_000))
+            );
+            assert_eq!(
+                try_blur(&[0u8; 4], usize::MAX, 2, &rgba, 3),
+                Err(BlurError::Overflow)
+            );
+            assert_eq!(
+                try_blur::<u8>(&[], 2, 2, &Channels::Interleaved(0), 3),
+                Err(BlurError::ZeroDimensions)
+            );
+
+            let mut data = [0u8; 40];
+            let mut image = ImageViewMut::with_stride(&mut data, 3, 4, 11, rgba);
+            assert_eq!(
+                try_blur_view_in_place_with(&mut image, &Options::new(3)),
+                Err(BlurError::Stride {
+                    stride: 11,
+                    row: 12
+                })
+            );
+            image.stride = 13;
+            assert_eq!(
+                try_blur_region_with(
+                    &mut image,
+                    &Rect {
+                        x: 0,
+                        y: 0,
+                        width: 1,
+                        height: 1
+                    },
+                    &Options::new(3)
+                ),
+                Err(BlurError::BufferSize {
+                    expected: 51,
+                    actual: 40
+                })
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "Radius too large")]
+        fn radius_overflowing_accumulators_panics() {
+            blur(&[0u8; 4], 1, 1, &Channels::RGBA, usize::MAX / 2);
+        }
+
+        #[test]
+        #[should_panic(expected = "Mask data is shorter than its rows")]
+        fn short_mask_panics() {
+            let mut data = [0u8; 16];
+            let mask = ImageView::new(&[255; 3], 2, 2, Channels::Gray);
+            blur_masked(
+                &mut ImageViewMut::new(&mut data, 2, 2, Channels::RGBA),
+                &mask,
+                3,
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "at least one channel")]
+        fn no_channels_panics() {
+            blur::<u8>(&[], 3, 2, &Channels::Interleaved(0), 4);
+        }
+
+        #[cfg(feature = "rayon")]
+        #[test]
+        #[should_panic(expected = "at least one channel")]
+        fn no_channels_panics_in_parallel() {
+            blur_parallel::<u8>(&[], 3, 2, &Channels::Interleaved(0), 4, 0);
+        }
+    }
 }
@@ -1,127 +1,1032 @@
 use jpeg_decoder::{Decoder as JPG, PixelFormat};
-use png::{BitDepth, ColorType, Decoder as PNG, Encoder, ScaledFloat, SourceChromaticities};
+use jpeg_encoder::{ColorType as JpegColorType, Encoder as JpegEncoder, SamplingFactor};
+use png::chunk::ChunkType;
+use png::{BitDepth, BlendOp, ColorType, Decoder as PNG, DisposeOp, Encoder, Transformations};
 use stackblur::stackblur;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use std::path::Path;
+use std::io::{BufWriter, Read, Write};
 use std::time::SystemTime;
 
 use clap::Parser;
 
-/// Blur png images
+/// Blur PNG and JPEG images, and BMP, TGA, PNM, TIFF, GIF and WebP images when
+/// built with their features
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Source
+    /// Source, or - for standard input
     #[clap(short, long)]
     source: String,
 
-    /// Destination
+    /// Destination, or - for standard output
     #[clap(short, long)]
     destination: String,
 
+    /// Format of the source [default: detected from its contents, then its
+    /// extension]
+    #[clap(long, arg_enum)]
+    input_format: Option<Format>,
+
+    /// Format of the destination [default: from its extension, then the
+    /// format of the source]
+    #[clap(long, arg_enum)]
+    output_format: Option<Format>,
+
     /// Radius
     #[clap(short, long, default_value_t = 20)]
-    radius: u8,
+    radius: usize,
+
+    /// Horizontal radius, 0 to skip the horizontal pass [default: radius]
+    #[clap(long)]
+    radius_x: Option<usize>,
+
+    /// Vertical radius, 0 to skip the vertical pass [default: radius]
+    #[clap(long)]
+    radius_y: Option<usize>,
+
+    /// Only blur this rectangle, given as x,y,width,height
+    #[clap(long, parse(try_from_str = parse_region))]
+    region: Option<stackblur::Rect>,
+
+    /// PNG the size of the source scaling the radius of every pixel, from
+    /// black for none to white for the full radius
+    #[clap(long, conflicts_with = "region")]
+    mask: Option<String>,
+
+    /// Blur with colours premultiplied by alpha, so transparent pixels don't
+    /// bleed into their neighbours
+    #[clap(long)]
+    premultiplied: bool,
+
+    /// Blur in linear light, treating colours as sRGB encoded
+    #[clap(long)]
+    linear: bool,
+
+    /// Leave out the colour profile, gamma, text and other metadata of the
+    /// source
+    #[clap(long)]
+    strip_metadata: bool,
+
+    /// Quality of JPEG destinations, from 1 to 100
+    #[clap(long, default_value_t = 90, parse(try_from_str = parse_quality))]
+    quality: u8,
+
+    /// Pixels sampled past the edges of the image
+    #[clap(long, arg_enum, default_value = "clamp")]
+    edge: Edge,
+
+    /// Only blur these channels, given as comma separated indices, like 3 for
+    /// the alpha of RGBA images [default: all]
+    #[clap(long, parse(try_from_str = parse_channels))]
+    channels: Option<u64>,
+
+    /// Number of threads to blur with, 0 for one per core
+    #[cfg(feature = "rayon")]
+    #[clap(long, default_value_t = 0)]
+    threads: usize,
 }
 
-fn main() {
-    let args = Args::parse();
+#[derive(clap::ArgEnum, Clone, Copy, Debug)]
+enum Edge {
+    Clamp,
+    Mirror,
+    Wrap,
+    /// Zero in every channel, so transparent black or black
+    Transparent,
+}
+
+fn parse_region(s: &str) -> Result<stackblur::Rect, String> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<usize>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [x, y, width, height] => Ok(stackblur::Rect {
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => Err("expected x,y,width,height".to_string()),
+    }
+}
+
+// Grayscale samples of a PNG, averaging the colour channels
+fn load_mask(path: &str) -> (Vec<u8>, usize, usize) {
+    let mut decoder = PNG::new(File::open(path).expect("Failed to open mask file"));
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().expect("Failed to decode the mask");
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buf)
+        .expect("Failed to read mask data");
+    let colours = match reader.output_color_type().0 {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => 1,
+        _ => 3,
+    };
+    let cc = info.buffer_size() / (info.width * info.height) as usize;
+    let gray = buf[..info.buffer_size()]
+        .chunks_exact(cc)
+        .map(|p| (p[..colours].iter().map(|&v| v as usize).sum::<usize>() / colours) as u8)
+        .collect();
+    (gray, info.width as usize, info.height as usize)
+}
+
+// Channel mask with the bits of the given indices set
+fn parse_channels(s: &str) -> Result<u64, String> {
+    s.split(',').try_fold(0, |mask, c| {
+        match c.trim().parse::<u32>().map_err(|e| e.to_string())? {
+            c if c < 64 => Ok(mask | 1 << c),
+            _ => Err("channels go from 0 to 63".to_string()),
+        }
+    })
+}
 
-    enum Extension {
-        PNG,
-        JPG,
+fn parse_quality(s: &str) -> Result<u8, String> {
+    match s.trim().parse::<u8>().map_err(|e| e.to_string())? {
+        q @ 1..=100 => Ok(q),
+        _ => Err("quality goes from 1 to 100".to_string()),
     }
+}
+
+fn blur_samples<T: stackblur::Sample>(
+    samples: &mut [T],
+    width: usize,
+    height: usize,
+    channels: stackblur::Channels,
+    mask: Option<&[u8]>,
+    region: Option<stackblur::Rect>,
+    args: &Args,
+) {
+    let options = stackblur::Options {
+        radius_x: args.radius_x.unwrap_or(args.radius),
+        radius_y: args.radius_y.unwrap_or(args.radius),
+        alpha: if args.premultiplied {
+            stackblur::Alpha::Premultiply
+        } else {
+            stackblur::Alpha::Independent
+        },
+        gamma: if args.linear {
+            stackblur::Gamma::Linear
+        } else {
+            stackblur::Gamma::Encoded
+        },
+        edge: match args.edge {
+            Edge::Clamp => stackblur::Edge::Clamp,
+            Edge::Mirror => stackblur::Edge::Mirror,
+            Edge::Wrap => stackblur::Edge::Wrap,
+            Edge::Transparent => stackblur::Edge::Constant([T::default(); 4]),
+        },
+        channel_mask: args.channels.unwrap_or(u64::MAX),
+        ..stackblur::Options::new(0)
+    };
+    #[cfg(feature = "rayon")]
+    let options = stackblur::Options {
+        threads: args.threads,
+        ..options
+    };
+    let mut image = stackblur::ImageViewMut::new(samples, width, height, channels);
+    match (region, mask) {
+        (Some(region), _) => stackblur::blur_region_with(&mut image, &region, &options),
+        (None, Some(mask)) => {
+            let mask = stackblur::ImageView::new(mask, width, height, stackblur::Channels::Gray);
+            stackblur::blur_masked_with(&mut image, &mask, &options)
+        }
+        (None, None) => stackblur::blur_view_in_place_with(&mut image, &options),
+    }
+}
 
-    let s_ext = if args.source.to_lowercase().ends_with(".png") {
-        Extension::PNG
-    } else if args.source.to_lowercase().ends_with(".jpg") {
-        Extension::JPG
+/// Formats other than PNG and JPEG need the cargo feature of the same name
+#[derive(clap::ArgEnum, Clone, Copy, Debug)]
+enum Format {
+    Png,
+    Jpeg,
+    Bmp,
+    Tga,
+    /// PBM, PGM, PPM or PAM
+    Pnm,
+    Tiff,
+    Gif,
+    Webp,
+}
+
+// Format from the signature at the start of the data. TGA doesn't have one.
+fn sniff(data: &[u8]) -> Option<Format> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(Format::Png)
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(Format::Jpeg)
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some(Format::Gif)
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        Some(Format::Webp)
+    } else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+        Some(Format::Tiff)
+    } else if data.starts_with(b"BM") {
+        Some(Format::Bmp)
+    } else if matches!(data, [b'P', b'1'..=b'7', b' ' | b'\t' | b'\r' | b'\n', ..]) {
+        Some(Format::Pnm)
     } else {
-        panic!("File type not supported")
-    };
-
-    let width;
-    let height;
-    let channels;
-    let mut buf;
-    let bytes;
-
-    match s_ext {
-        Extension::PNG => {
-            let decoder = PNG::new(File::open(&args.source).expect("Failed to open source file"));
-            let mut reader = decoder.read_info().expect("Failed to decode the image");
-            buf = vec![0; reader.output_buffer_size()];
-            let info = reader.next_frame(&mut buf).expect("Failed to read data");
-            bytes = &buf[..info.buffer_size()];
-            width = info.width as usize;
-            height = info.height as usize;
-            channels = match info.color_type {
-                ColorType::Rgb => stackblur::Channels::RGB,
-                ColorType::Rgba => stackblur::Channels::RGBA,
-                _ => panic!("Only supports rgba and rgb color types"),
+        None
+    }
+}
+
+fn extension(path: &str) -> Option<Format> {
+    let (_, extension) = path.rsplit_once('.')?;
+    match &extension.to_lowercase()[..] {
+        "png" => Some(Format::Png),
+        "jpg" | "jpeg" => Some(Format::Jpeg),
+        "bmp" => Some(Format::Bmp),
+        "tga" => Some(Format::Tga),
+        "pbm" | "pgm" | "ppm" | "pnm" | "pam" => Some(Format::Pnm),
+        "tif" | "tiff" => Some(Format::Tiff),
+        "gif" => Some(Format::Gif),
+        "webp" => Some(Format::Webp),
+        _ => None,
+    }
+}
+
+// Decoded image, with a single frame unless it's animated
+struct Image {
+    width: usize,
+    height: usize,
+    channels: stackblur::Channels,
+    depth: BitDepth,
+    frames: Vec<Frame>,
+    animation: Option<Animation>,
+    metadata: Metadata,
+}
+
+// Samples laid out like those of a PNG, so with 16-bit samples big-endian,
+// covering part of the canvas
+struct Frame {
+    samples: Vec<u8>,
+    rect: stackblur::Rect,
+    // Seconds the frame is shown, as a fraction
+    delay: (u16, u16),
+    dispose: DisposeOp,
+    blend: BlendOp,
+}
+
+struct Animation {
+    // Times to play the animation, 0 for forever
+    plays: u32,
+    // The first frame is a still shown by viewers without animation support,
+    // rather than part of the animation
+    still_first: bool,
+}
+
+// Ancillary data of the source, carried over to the destination
+#[derive(Default)]
+struct Metadata {
+    // Chunks of a PNG source, copied verbatim to PNG destinations
+    chunks: Vec<([u8; 4], Vec<u8>)>,
+    // Uncompressed colour profile
+    icc: Option<Vec<u8>>,
+    // TIFF structure holding the Exif tags
+    exif: Option<Vec<u8>>,
+}
+
+// Ancillary chunks carried over from PNG sources
+const KEPT_CHUNKS: [&[u8; 4]; 9] = [
+    b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"pHYs", b"tEXt", b"zTXt", b"iTXt", b"eXIf",
+];
+
+impl Image {
+    fn still(
+        samples: Vec<u8>,
+        width: usize,
+        height: usize,
+        channels: stackblur::Channels,
+        depth: BitDepth,
+    ) -> Image {
+        Image {
+            width,
+            height,
+            channels,
+            depth,
+            frames: vec![Frame {
+                samples,
+                rect: stackblur::Rect {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                },
+                delay: (0, 100),
+                dispose: DisposeOp::None,
+                blend: BlendOp::Source,
+            }],
+            animation: None,
+            metadata: Metadata::default(),
+        }
+    }
+
+    // Samples of the whole canvas as shown by formats without animation: the
+    // first frame, on a transparent background if it doesn't cover the canvas
+    fn into_still(mut self) -> Vec<u8> {
+        let frame = self.frames.swap_remove(0);
+        if (frame.rect.width, frame.rect.height) == (self.width, self.height) {
+            return frame.samples;
+        }
+        let pixel = frame.samples.len() / (frame.rect.width * frame.rect.height);
+        let mut samples = vec![0; self.width * self.height * pixel];
+        for (y, row) in frame
+            .samples
+            .chunks_exact(frame.rect.width * pixel)
+            .enumerate()
+        {
+            let at = ((frame.rect.y + y) * self.width + frame.rect.x) * pixel;
+            samples[at..at + row.len()].copy_from_slice(row);
+        }
+        samples
+    }
+}
+
+// Blurs every frame on its own. The region and mask cover the canvas, so
+// they're cut down to the part under each frame.
+fn blur_image(image: &mut Image, mask: Option<&[u8]>, args: &Args) {
+    for frame in &mut image.frames {
+        let rect = frame.rect;
+        let region = args.region.map(|region| {
+            let (x, y) = (
+                region.x.saturating_sub(rect.x),
+                region.y.saturating_sub(rect.y),
+            );
+            stackblur::Rect {
+                x,
+                y,
+                width: region
+                    .x
+                    .saturating_add(region.width)
+                    .saturating_sub(rect.x + x),
+                height: region
+                    .y
+                    .saturating_add(region.height)
+                    .saturating_sub(rect.y + y),
+            }
+        });
+        let mask: Option<Vec<u8>> = mask.map(|mask| {
+            mask.chunks_exact(image.width)
+                .skip(rect.y)
+                .take(rect.height)
+                .flat_map(|row| &row[rect.x..rect.x + rect.width])
+                .copied()
+                .collect()
+        });
+
+        // PNG stores 16-bit samples big-endian
+        match image.depth {
+            BitDepth::Sixteen => {
+                let mut samples: Vec<u16> = frame
+                    .samples
+                    .chunks_exact(2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                    .collect();
+                blur_samples(
+                    &mut samples,
+                    rect.width,
+                    rect.height,
+                    image.channels,
+                    mask.as_deref(),
+                    region,
+                    args,
+                );
+                frame.samples = samples.iter().flat_map(|s| s.to_be_bytes()).collect();
+            }
+            _ => blur_samples(
+                &mut frame.samples,
+                rect.width,
+                rect.height,
+                image.channels,
+                mask.as_deref(),
+                region,
+                args,
+            ),
+        }
+    }
+}
+
+// Samples scaled down to 8 bits if they're 16
+fn eight_bit(samples: Vec<u8>, depth: BitDepth) -> Vec<u8> {
+    match depth {
+        BitDepth::Sixteen => samples
+            .chunks_exact(2)
+            .map(|b| ((u16::from_be_bytes([b[0], b[1]]) as u32 + 128) / 257) as u8)
+            .collect(),
+        _ => samples,
+    }
+}
+
+fn decode_png(data: &[u8]) -> Image {
+    let decoder = PNG::new(data);
+    let mut reader = decoder.read_info().expect("Failed to decode the image");
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).expect("Failed to read data");
+    let depth = match info.bit_depth {
+        BitDepth::Eight | BitDepth::Sixteen => info.bit_depth,
+        _ => panic!("Only supports 8 and 16 bit images"),
+    };
+    let channels = match info.color_type {
+        ColorType::Rgb => stackblur::Channels::RGB,
+        ColorType::Rgba => stackblur::Channels::RGBA,
+        ColorType::Grayscale => stackblur::Channels::Gray,
+        ColorType::GrayscaleAlpha => stackblur::Channels::GrayAlpha,
+        _ => panic!("Only supports rgba, rgb, grayscale and grayscale alpha color types"),
+    };
+    let (width, height) = (reader.info().width as usize, reader.info().height as usize);
+    let mut image = Image::still(
+        buf[..info.buffer_size()].to_vec(),
+        width,
+        height,
+        channels,
+        depth,
+    );
+    let chunks: Vec<([u8; 4], Vec<u8>)> = png_chunks(data)
+        .filter(|(kind, _)| KEPT_CHUNKS.contains(&kind))
+        .map(|(kind, contents)| (kind, contents.to_vec()))
+        .collect();
+    image.metadata = Metadata {
+        // Inflated here, as the decoder drops the end of longer profiles
+        icc: chunks
+            .iter()
+            .find(|(kind, _)| kind == b"iCCP")
+            .and_then(|(_, contents)| {
+                // Past the profile name and compression method
+                let name = contents.iter().position(|&b| b == 0)?;
+                miniz_oxide::inflate::decompress_to_vec_zlib(contents.get(name + 2..)?).ok()
+            }),
+        exif: chunks
+            .iter()
+            .find(|(kind, _)| kind == b"eXIf")
+            .map(|(_, contents)| contents.clone()),
+        chunks,
+    };
+
+    let Some(control) = reader.info().animation_control().copied() else {
+        return image;
+    };
+    // Without a frame control ahead of it, the first image isn't part of the
+    // animation
+    let still_first = reader.info().frame_control().is_none();
+    if !still_first {
+        image.frames.clear();
+    }
+    for i in 0..control.num_frames {
+        // The first image was already read when it starts the animation
+        let size = if i == 0 && !still_first {
+            info.buffer_size()
+        } else {
+            reader
+                .next_frame(&mut buf)
+                .expect("Failed to read data")
+                .buffer_size()
+        };
+        let control = reader
+            .info()
+            .frame_control()
+            .copied()
+            .expect("Animation frames have a frame control");
+        image.frames.push(Frame {
+            samples: buf[..size].to_vec(),
+            rect: stackblur::Rect {
+                x: control.x_offset as usize,
+                y: control.y_offset as usize,
+                width: control.width as usize,
+                height: control.height as usize,
+            },
+            delay: (control.delay_num, control.delay_den),
+            dispose: control.dispose_op,
+            blend: control.blend_op,
+        });
+    }
+    image.animation = Some(Animation {
+        plays: control.num_plays,
+        still_first,
+    });
+    image
+}
+
+fn decode_jpeg(data: &[u8]) -> Image {
+    let mut decoder = JPG::new(data);
+    let samples = decoder.decode().expect("Failed to decode");
+    let metadata = decoder
+        .info()
+        .expect("Something went wrong while reading info");
+    let channels = match metadata.pixel_format {
+        PixelFormat::RGB24 => stackblur::Channels::RGB,
+        PixelFormat::L8 => stackblur::Channels::Gray,
+        _ => panic!("Only supports rgb and grayscale pixel formats"),
+    };
+    let mut image = Image::still(
+        samples,
+        metadata.width as usize,
+        metadata.height as usize,
+        channels,
+        BitDepth::Eight,
+    );
+
+    // Profiles too large for one segment are split over several, numbered
+    // from 1
+    let mut icc = vec![];
+    for (marker, segment) in jpeg_segments(data) {
+        match marker {
+            0xE1 => {
+                if let Some(exif) = segment.strip_prefix(b"Exif\0\0") {
+                    image.metadata.exif.get_or_insert_with(|| exif.to_vec());
+                }
+            }
+            0xE2 => {
+                if let Some([number, _, profile @ ..]) = segment.strip_prefix(b"ICC_PROFILE\0") {
+                    icc.push((*number, profile));
+                }
+            }
+            _ => {}
+        }
+    }
+    icc.sort_by_key(|&(number, _)| number);
+    if !icc.is_empty() {
+        image.metadata.icc = Some(
+            icc.into_iter()
+                .flat_map(|(_, profile)| profile)
+                .copied()
+                .collect(),
+        );
+    }
+    image
+}
+
+#[cfg(feature = "gif")]
+fn decode_gif(data: &[u8]) -> Image {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(data).expect("Failed to decode the image");
+    let mut frames = vec![];
+    while let Some(frame) = decoder.read_next_frame().expect("Failed to read data") {
+        frames.push(Frame {
+            samples: frame.buffer.to_vec(),
+            rect: stackblur::Rect {
+                x: frame.left as usize,
+                y: frame.top as usize,
+                width: frame.width as usize,
+                height: frame.height as usize,
+            },
+            delay: (frame.delay, 100),
+            dispose: match frame.dispose {
+                gif::DisposalMethod::Any | gif::DisposalMethod::Keep => DisposeOp::None,
+                gif::DisposalMethod::Background => DisposeOp::Background,
+                gif::DisposalMethod::Previous => DisposeOp::Previous,
+            },
+            // Transparent pixels let what's underneath show through
+            blend: BlendOp::Over,
+        });
+    }
+    if frames.is_empty() {
+        panic!("The image has no frames");
+    }
+    // GIF counts the repeats after the first play
+    let plays = match decoder.repeat() {
+        gif::Repeat::Infinite => 0,
+        gif::Repeat::Finite(repeats) => repeats as u32 + 1,
+    };
+    Image {
+        width: decoder.width() as usize,
+        height: decoder.height() as usize,
+        channels: stackblur::Channels::RGBA,
+        depth: BitDepth::Eight,
+        animation: (frames.len() > 1).then_some(Animation {
+            plays,
+            still_first: false,
+        }),
+        frames,
+        metadata: Metadata::default(),
+    }
+}
+
+#[cfg(not(feature = "gif"))]
+fn decode_gif(_: &[u8]) -> Image {
+    panic!("Built without support for {:?} images", Format::Gif)
+}
+
+#[cfg(feature = "image")]
+fn image_format(format: Format) -> image::ImageFormat {
+    match format {
+        Format::Png | Format::Jpeg | Format::Gif => {
+            unreachable!("read and written without the image crate")
+        }
+        Format::Bmp => image::ImageFormat::Bmp,
+        Format::Tga => image::ImageFormat::Tga,
+        Format::Pnm => image::ImageFormat::Pnm,
+        Format::Tiff => image::ImageFormat::Tiff,
+        Format::Webp => image::ImageFormat::WebP,
+    }
+}
+
+// Image in any other format
+#[cfg(feature = "image")]
+fn decode_image(data: &[u8], format: Format) -> Image {
+    use image::DynamicImage;
+
+    let image = image::load_from_memory_with_format(data, image_format(format))
+        .expect("Failed to decode the image");
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let big_endian = |samples: Vec<u16>| samples.iter().flat_map(|s| s.to_be_bytes()).collect();
+    let (samples, channels, depth) = match image {
+        DynamicImage::ImageLuma8(i) => (i.into_raw(), stackblur::Channels::Gray, BitDepth::Eight),
+        DynamicImage::ImageLumaA8(i) => (
+            i.into_raw(),
+            stackblur::Channels::GrayAlpha,
+            BitDepth::Eight,
+        ),
+        DynamicImage::ImageRgb8(i) => (i.into_raw(), stackblur::Channels::RGB, BitDepth::Eight),
+        DynamicImage::ImageRgba8(i) => (i.into_raw(), stackblur::Channels::RGBA, BitDepth::Eight),
+        DynamicImage::ImageLuma16(i) => (
+            big_endian(i.into_raw()),
+            stackblur::Channels::Gray,
+            BitDepth::Sixteen,
+        ),
+        DynamicImage::ImageLumaA16(i) => (
+            big_endian(i.into_raw()),
+            stackblur::Channels::GrayAlpha,
+            BitDepth::Sixteen,
+        ),
+        DynamicImage::ImageRgb16(i) => (
+            big_endian(i.into_raw()),
+            stackblur::Channels::RGB,
+            BitDepth::Sixteen,
+        ),
+        // Floating point images are blurred as 16-bit
+        image => (
+            big_endian(image.into_rgba16().into_raw()),
+            stackblur::Channels::RGBA,
+            BitDepth::Sixteen,
+        ),
+    };
+    Image::still(samples, width, height, channels, depth)
+}
+
+#[cfg(not(feature = "image"))]
+fn decode_image(_: &[u8], format: Format) -> Image {
+    panic!("Built without support for {:?} images", format)
+}
+
+fn encode_png(w: impl Write, image: Image) {
+    let color_type = match image.channels {
+        stackblur::Channels::RGB => ColorType::Rgb,
+        stackblur::Channels::RGBA => ColorType::Rgba,
+        stackblur::Channels::Gray => ColorType::Grayscale,
+        stackblur::Channels::GrayAlpha => ColorType::GrayscaleAlpha,
+        stackblur::Channels::Interleaved(_) => {
+            unreachable!("decoded images have a color type")
+        }
+    };
+    let mut encoder = Encoder::new(w, image.width as u32, image.height as u32);
+    encoder.set_color(color_type);
+    encoder.set_depth(image.depth);
+
+    // Chunks of a PNG source go in as they were, and the profile and Exif of
+    // other sources in their PNG form
+    let metadata = &image.metadata;
+    let mut chunks = metadata.chunks.clone();
+    let has = |kind| metadata.chunks.iter().any(|(k, _)| k == kind);
+    if let (Some(icc), false) = (&metadata.icc, has(b"iCCP")) {
+        let mut contents = b"ICC profile\0\0".to_vec();
+        contents.extend(miniz_oxide::deflate::compress_to_vec_zlib(icc, 6));
+        chunks.push((*b"iCCP", contents));
+    }
+    if let (Some(exif), false) = (&metadata.exif, has(b"eXIf")) {
+        chunks.push((*b"eXIf", exif.clone()));
+    }
+    let write_header = |encoder: Encoder<'static, _>| {
+        let mut writer = encoder
+            .write_header()
+            .expect("Failed to encode (write header)");
+        for (kind, contents) in &chunks {
+            writer
+                .write_chunk(ChunkType(*kind), contents)
+                .expect("Failed to encode the metadata");
+        }
+        writer
+    };
+
+    let Some(animation) = &image.animation else {
+        let mut writer = write_header(encoder);
+        writer
+            .write_image_data(&image.into_still())
+            .expect("Failed to encode");
+        writer.finish().expect("Failed to encode");
+        return;
+    };
+    let frames = image.frames.len() - animation.still_first as usize;
+    encoder
+        .set_animated(frames as u32, animation.plays)
+        .expect("Failed to encode the animation");
+    encoder
+        .set_sep_def_img(animation.still_first)
+        .expect("Failed to encode the animation");
+    let mut writer = write_header(encoder);
+    for (i, frame) in image.frames.iter().enumerate() {
+        if i > 0 || !animation.still_first {
+            let rect = &frame.rect;
+            // Bounds are checked against the current position and size, so
+            // the frame moves to the origin before being resized
+            writer
+                .reset_frame_position()
+                .and_then(|_| writer.set_frame_dimension(rect.width as u32, rect.height as u32))
+                .and_then(|_| writer.set_frame_position(rect.x as u32, rect.y as u32))
+                .and_then(|_| writer.set_frame_delay(frame.delay.0, frame.delay.1))
+                .and_then(|_| writer.set_dispose_op(frame.dispose))
+                .and_then(|_| writer.set_blend_op(frame.blend))
+                .expect("Failed to encode the animation");
+        }
+        writer
+            .write_image_data(&frame.samples)
+            .expect("Failed to encode");
+    }
+    writer.finish().expect("Failed to encode");
+}
+
+fn encode_jpeg(w: impl Write, mut image: Image, quality: u8, sampling: Option<SamplingFactor>) {
+    let (width, height, channels) = (image.width, image.height, image.channels);
+    let metadata = std::mem::take(&mut image.metadata);
+    // JPEG only holds 8-bit samples, and no alpha in grayscale
+    let depth = image.depth;
+    let samples = eight_bit(image.into_still(), depth);
+    let (samples, color_type) = match channels {
+        stackblur::Channels::RGB => (samples, JpegColorType::Rgb),
+        stackblur::Channels::RGBA => (samples, JpegColorType::Rgba),
+        stackblur::Channels::Gray => (samples, JpegColorType::Luma),
+        stackblur::Channels::GrayAlpha => {
+            let gray = samples.chunks_exact(2).map(|p| p[0]).collect();
+            (gray, JpegColorType::Luma)
+        }
+        stackblur::Channels::Interleaved(_) => {
+            unreachable!("decoded images have a color type")
+        }
+    };
+    let mut encoder = JpegEncoder::new(w, quality);
+    if let Some(sampling) = sampling {
+        encoder.set_sampling_factor(sampling);
+    }
+    if let Some(icc) = &metadata.icc {
+        encoder
+            .add_icc_profile(icc)
+            .expect("Failed to encode the colour profile");
+    }
+    if let Some(exif) = &metadata.exif {
+        encoder
+            .add_app_segment(1, &[&b"Exif\0\0"[..], exif].concat())
+            .expect("Failed to encode the Exif");
+    }
+    let size =
+        |n: usize| u16::try_from(n).expect("JPEG images are at most 65535 pixels wide or high");
+    encoder
+        .encode(&samples, size(width), size(height), color_type)
+        .expect("Failed to encode");
+}
+
+#[cfg(feature = "gif")]
+fn encode_gif(w: impl Write, image: Image) {
+    let size =
+        |n: usize| u16::try_from(n).expect("GIF images are at most 65535 pixels wide or high");
+    let mut encoder = gif::Encoder::new(w, size(image.width), size(image.height), &[])
+        .expect("Failed to encode (write header)");
+    let skip = match &image.animation {
+        Some(animation) => {
+            let repeat = match animation.plays {
+                0 => gif::Repeat::Infinite,
+                plays => gif::Repeat::Finite((plays - 1).min(u16::MAX as u32) as u16),
             };
+            encoder
+                .set_repeat(repeat)
+                .expect("Failed to encode the animation");
+            // GIF has nowhere to keep a still apart from the animation
+            animation.still_first as usize
+        }
+        None => 0,
+    };
+
+    for frame in image.frames.into_iter().skip(skip) {
+        let pixels = frame.rect.width * frame.rect.height;
+        let samples = eight_bit(frame.samples, image.depth);
+        let cc = samples.len() / pixels;
+        // Frames are quantized from RGBA, with gray spread over the colours
+        let mut rgba: Vec<u8> = samples
+            .chunks_exact(cc)
+            .flat_map(|p| match p {
+                [v] => [*v, *v, *v, 0xFF],
+                [v, a] => [*v, *v, *v, *a],
+                [r, g, b] => [*r, *g, *b, 0xFF],
+                [r, g, b, a] => [*r, *g, *b, *a],
+                _ => unreachable!("decoded images have a color type"),
+            })
+            .collect();
+        let mut out = gif::Frame::from_rgba_speed(
+            size(frame.rect.width),
+            size(frame.rect.height),
+            &mut rgba,
+            10,
+        );
+        out.left = size(frame.rect.x);
+        out.top = size(frame.rect.y);
+        // In hundredths of a second, where a zero denominator means 100
+        let den = match frame.delay.1 {
+            0 => 100,
+            den => den as u32,
+        };
+        out.delay = ((frame.delay.0 as u32 * 100 + den / 2) / den).min(u16::MAX as u32) as u16;
+        // Frames always blend over the canvas, since GIF has no other way
+        out.dispose = match frame.dispose {
+            DisposeOp::None => gif::DisposalMethod::Keep,
+            DisposeOp::Background => gif::DisposalMethod::Background,
+            DisposeOp::Previous => gif::DisposalMethod::Previous,
+        };
+        encoder.write_frame(&out).expect("Failed to encode");
+    }
+}
+
+#[cfg(not(feature = "gif"))]
+fn encode_gif(_: impl Write, _: Image) {
+    panic!("Built without support for {:?} images", Format::Gif)
+}
+
+// Writes an image in any other format, converting it to the closest colour
+// type and depth the format supports
+#[cfg(feature = "image")]
+fn encode_image(mut w: impl Write, image: Image, format: Format) {
+    use image::{DynamicImage, ImageBuffer};
+
+    let (width, height) = (image.width as u32, image.height as u32);
+    let (channels, depth) = (image.channels, image.depth);
+    let samples = image.into_still();
+    let image = match depth {
+        BitDepth::Sixteen => {
+            let samples: Vec<u16> = samples
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                .collect();
+            match channels {
+                stackblur::Channels::Gray => {
+                    ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLuma16)
+                }
+                stackblur::Channels::GrayAlpha => {
+                    ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLumaA16)
+                }
+                stackblur::Channels::RGB => {
+                    ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgb16)
+                }
+                stackblur::Channels::RGBA => {
+                    ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgba16)
+                }
+                stackblur::Channels::Interleaved(_) => {
+                    unreachable!("decoded images have a color type")
+                }
+            }
         }
-        Extension::JPG => {
-            let file = File::open(&args.source).expect("failed to open file");
-            let mut decoder = JPG::new(BufReader::new(file));
-            buf = decoder.decode().expect("Failed to decode");
-            let metadata = decoder
-                .info()
-                .expect("Something went wrong while reading info");
-            bytes = &buf[..];
-            width = metadata.width as usize;
-            height = metadata.height as usize;
-            channels = match metadata.pixel_format {
-                PixelFormat::RGB24 => stackblur::Channels::RGB,
-                _ => panic!("Only supports rgba and rgb color types"),
+        _ => match channels {
+            stackblur::Channels::Gray => {
+                ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLuma8)
+            }
+            stackblur::Channels::GrayAlpha => {
+                ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLumaA8)
+            }
+            stackblur::Channels::RGB => {
+                ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgb8)
             }
+            stackblur::Channels::RGBA => {
+                ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgba8)
+            }
+            stackblur::Channels::Interleaved(_) => {
+                unreachable!("decoded images have a color type")
+            }
+        },
+    }
+    .expect("The samples don't fill the image");
+
+    // Some encoders seek, so the image is encoded in memory first
+    let mut encoded = std::io::Cursor::new(vec![]);
+    image
+        .write_to(&mut encoded, image_format(format))
+        .expect("Failed to encode");
+    w.write_all(encoded.get_ref())
+        .expect("Failed to write the destination");
+}
+
+#[cfg(not(feature = "image"))]
+fn encode_image(_: impl Write, _: Image, format: Format) {
+    panic!("Built without support for {:?} images", format)
+}
+
+// Chunks of a PNG, as type and contents
+fn png_chunks(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut at = 8;
+    std::iter::from_fn(move || {
+        let len = u32::from_be_bytes(data.get(at..at + 4)?.try_into().unwrap()) as usize;
+        let kind = data.get(at + 4..at + 8)?.try_into().unwrap();
+        let contents = data.get(at + 8..at + 8 + len)?;
+        at += 12 + len;
+        Some((kind, contents))
+    })
+}
+
+// Marker segments of a JPEG ahead of its first scan, as marker and contents
+fn jpeg_segments(data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut at = 2;
+    std::iter::from_fn(move || loop {
+        if at + 4 > data.len() || data[at] != 0xFF {
+            return None;
         }
+        let marker = data[at + 1];
+        // Markers may be padded with any number of fill bytes
+        if marker == 0xFF {
+            at += 1;
+            continue;
+        }
+        // Start of scan, past which there are no more frame headers
+        if marker == 0xDA {
+            return None;
+        }
+        let len = u16::from_be_bytes([data[at + 2], data[at + 3]]) as usize;
+        let segment = data.get(at + 4..at + 2 + len)?;
+        at += 2 + len;
+        return Some((marker, segment));
+    })
+}
+
+// Sampling factors of the luma component of a JPEG, from its frame header.
+// Chroma is usually sampled at 1x1, which makes these its subsampling too.
+fn jpeg_sampling(data: &[u8]) -> Option<SamplingFactor> {
+    // SOF markers, leaving out DHT, JPG and DAC which share the range
+    let (_, segment) = jpeg_segments(data).find(|&(marker, _)| {
+        matches!(marker, 0xC0..=0xCF) && ![0xC4, 0xC8, 0xCC].contains(&marker)
+    })?;
+    let factors = segment.get(7)?;
+    SamplingFactor::from_factors(factors >> 4, factors & 0x0F)
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let data = if args.source == "-" {
+        let mut data = vec![];
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .expect("Failed to read standard input");
+        data
+    } else {
+        std::fs::read(&args.source).expect("Failed to open source file")
+    };
+    let format = args
+        .input_format
+        .or_else(|| sniff(&data))
+        .or_else(|| extension(&args.source))
+        .expect("File type not supported");
+
+    let (mut image, sampling) = match format {
+        Format::Png => (decode_png(&data), None),
+        Format::Jpeg => (decode_jpeg(&data), jpeg_sampling(&data)),
+        Format::Gif => (decode_gif(&data), None),
+        _ => (decode_image(&data, format), None),
+    };
+    if args.strip_metadata {
+        image.metadata = Metadata::default();
     }
 
+    let mask = args.mask.as_deref().map(|path| {
+        let (mask, mask_width, mask_height) = load_mask(path);
+        if (mask_width, mask_height) != (image.width, image.height) {
+            panic!("The mask isn't the size of the source");
+        }
+        mask
+    });
+
     let start_time = SystemTime::now();
 
-    let hmm = stackblur::blur(bytes, width, height, &channels, args.radius as usize);
+    blur_image(&mut image, mask.as_deref(), &args);
 
     let elapsed = start_time.elapsed().expect("Failed to measure time");
-    println!("Generated in: {:?} milliseconds", elapsed.as_millis());
+    // Keep standard output clean when the image goes there
+    if args.destination == "-" {
+        eprintln!("Generated in: {:?} milliseconds", elapsed.as_millis());
+    } else {
+        println!("Generated in: {:?} milliseconds", elapsed.as_millis());
+    }
 
-    let d_ext = if args.destination.to_lowercase().ends_with(".png") {
-        Extension::PNG
-    } else if args.source.to_lowercase().ends_with(".jpg") {
-        Extension::JPG
+    let w: Box<dyn Write> = if args.destination == "-" {
+        Box::new(BufWriter::new(std::io::stdout().lock()))
     } else {
-        panic!("File type not supported")
+        let file = File::create(&args.destination).expect("Failed to create destination file");
+        Box::new(BufWriter::new(file))
     };
+    let output_format = args
+        .output_format
+        .or_else(|| extension(&args.destination))
+        .unwrap_or(format);
 
-    match d_ext {
-        Extension::PNG => {
-            let path = Path::new(&args.destination);
-            let file = File::create(path).expect("Failed to create destination file");
-            let ref mut w = BufWriter::new(file);
-
-            let color_type = match channels {
-                stackblur::Channels::RGB => ColorType::Rgb,
-                stackblur::Channels::RGBA => ColorType::Rgba,
-            };
-            let mut encoder = Encoder::new(w, width as u32, height as u32);
-            encoder.set_color(color_type);
-            encoder.set_depth(BitDepth::Eight);
-            encoder.set_trns(vec![0xFFu8, 0xFFu8, 0xFFu8, 0xFFu8]);
-            encoder.set_source_gamma(ScaledFloat::from_scaled(45455));
-            encoder.set_source_gamma(ScaledFloat::new(1.0 / 2.2));
-            let source_chromaticities = SourceChromaticities::new(
-                (0.31270, 0.32900),
-                (0.64000, 0.33000),
-                (0.30000, 0.60000),
-                (0.15000, 0.06000),
-            );
-            encoder.set_source_chromaticities(source_chromaticities);
-            let mut writer = encoder
-                .write_header()
-                .expect("Failed to encode (write header)");
-            writer.write_image_data(&hmm[..]).expect("Failed to encode");
-        }
-        Extension::JPG => panic!("JPEG output isn't supported yet"),
+    match output_format {
+        Format::Png => encode_png(w, image),
+        Format::Jpeg => encode_jpeg(w, image, args.quality, sampling),
+        Format::Gif => encode_gif(w, image),
+        _ => encode_image(w, image, output_format),
     }
 }
@@ -0,0 +1,81 @@
+use super::{blur_strip, ImageViewMut, Options, Processor, Sample, STRIP_WIDTH};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::{Arc, Mutex, PoisonError};
+
+// Runs `op` on a pool of `threads` threads. The last pool built is kept, so
+// repeated blurs with the same count don't spawn threads every call, and asking
+// for another count replaces it rather than keeping one pool per count.
+pub(super) fn install<R: Send>(threads: usize, op: impl FnOnce() -> R + Send) -> R {
+    static POOL: Mutex<Option<(usize, Arc<ThreadPool>)>> = Mutex::new(None);
+    let pool = {
+        let mut last = POOL.lock().unwrap_or_else(PoisonError::into_inner);
+        match &*last {
+            Some((count, pool)) if *count == threads => pool.clone(),
+            _ => {
+                let pool = ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .expect("Failed to build the thread pool");
+                let pool = Arc::new(pool);
+                *last = Some((threads, pool.clone()));
+                pool
+            }
+        }
+    };
+    pool.install(op)
+}
+
+pub(super) fn blur<T: Sample>(image: &mut ImageViewMut<T>, options: &Options<T>) {
+    let (width, height, stride) = (image.width, image.height, image.stride);
+    let channels = image.channels;
+    let cc = channels.count();
+
+    if options.radius_x > 0 {
+        image
+            .data
+            .par_chunks_mut(stride)
+            .take(height)
+            .for_each_init(
+                || {
+                    Processor::new(
+                        channels,
+                        options.radius_x,
+                        options.edge,
+                        options.channel_mask,
+                    )
+                },
+                |processor, row| processor.blur_line(row, 0, cc, width),
+            );
+    }
+    if options.radius_y == 0 {
+        return;
+    }
+
+    // Cut every row into strips of columns, so each task owns a disjoint set of
+    // columns spanning the full height of the image
+    let mut strips: Vec<Vec<&mut [T]>> = (0..width.div_ceil(STRIP_WIDTH))
+        .map(|_| Vec::with_capacity(height))
+        .collect();
+    for row in image.data.chunks_mut(stride).take(height) {
+        let parts = row[..width * cc].chunks_mut(STRIP_WIDTH * cc);
+        for (strip, part) in strips.iter_mut().zip(parts) {
+            strip.push(part);
+        }
+    }
+
+    strips.into_par_iter().for_each_init(
+        || {
+            (
+                Processor::new(
+                    channels,
+                    options.radius_y,
+                    options.edge,
+                    options.channel_mask,
+                ),
+                vec![],
+            )
+        },
+        |(processor, scratch), mut rows| blur_strip(processor, &mut rows, scratch),
+    );
+}
@@ -0,0 +1,244 @@
+use std::ops::{AddAssign, SubAssign};
+
+/// Types of channel values the blur works on: `u8`, `u16` and `f32`
+///
+/// The trait is sealed, as the processors rely on how each type is summed and
+/// averaged.
+pub trait Sample: sealed::Sealed {}
+
+impl Sample for u8 {}
+impl Sample for u16 {}
+impl Sample for f32 {}
+
+pub(super) mod sealed {
+    use super::super::srgb;
+    use super::{AddAssign, Sample, SubAssign};
+
+    pub trait Sealed: Copy + Default + Send + Sync + 'static {
+        // Running sum of samples, wide enough for `MAX * radius^2` up to `SUM_MAX`
+        type Sum: Copy + Default + AddAssign + SubAssign + Send;
+
+        // Samples holding linear light with at least the precision of `Self`
+        type Linear: Sample;
+
+        // Largest sample, which bounds the sums. Float sums can't overflow, so
+        // floats only need `radius^2` to fit.
+        const MAX: u64;
+        // Largest value `Sum` holds
+        const SUM_MAX: u64;
+        // Whether samples are bytes. MUL_TABLE and SHG_TABLE are only accurate
+        // enough for bytes, which are also all the vector kernels handle.
+        const BYTES: bool;
+
+        fn weighted(self, weight: usize) -> Self::Sum;
+
+        // `(sum * mul) >> shg`, for radii covered by MUL_TABLE
+        fn mul_shr(sum: Self::Sum, mul: usize, shg: usize) -> Self;
+
+        fn divide(sum: Self::Sum, weight: usize) -> Self;
+
+        // The samples as bytes, if `BYTES`
+        fn bytes_mut(samples: &mut [Self]) -> Option<&mut [u8]>;
+
+        // Colour scaled by `alpha`, rounded to the nearest sample
+        fn premultiply(self, alpha: Self) -> Self;
+
+        // Inverse of `premultiply`, with fully transparent colours set to 0
+        fn unpremultiply(self, alpha: Self) -> Self;
+
+        // sRGB encoded colour to linear light, and back rounded to the nearest
+        // sample
+        fn decode_srgb(self) -> Self::Linear;
+        fn encode_srgb(linear: Self::Linear) -> Self;
+
+        // Same value in the range of `Linear`, for alpha which isn't encoded,
+        // and back rounded to the nearest sample
+        fn widen(self) -> Self::Linear;
+        fn narrow(linear: Self::Linear) -> Self;
+
+        // `self` moved a fraction `t` of the way to `other`, rounded to the
+        // nearest sample
+        fn mix(self, other: Self, t: f32) -> Self;
+    }
+
+    impl Sealed for u8 {
+        type Sum = usize;
+        type Linear = u16;
+
+        const MAX: u64 = u8::MAX as u64;
+        const SUM_MAX: u64 = usize::MAX as u64;
+        const BYTES: bool = true;
+
+        #[inline(always)]
+        fn weighted(self, weight: usize) -> usize {
+            self as usize * weight
+        }
+
+        #[inline(always)]
+        fn mul_shr(sum: usize, mul: usize, shg: usize) -> Self {
+            ((sum * mul) >> shg) as u8
+        }
+
+        #[inline(always)]
+        fn divide(sum: usize, weight: usize) -> Self {
+            (sum / weight) as u8
+        }
+
+        fn bytes_mut(samples: &mut [Self]) -> Option<&mut [u8]> {
+            Some(samples)
+        }
+
+        fn premultiply(self, alpha: Self) -> Self {
+            ((self as u32 * alpha as u32 + 127) / 255) as u8
+        }
+
+        fn unpremultiply(self, alpha: Self) -> Self {
+            match alpha {
+                0 => 0,
+                a => ((self as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8,
+            }
+        }
+
+        fn decode_srgb(self) -> u16 {
+            srgb::decode_u8(self)
+        }
+
+        fn encode_srgb(linear: u16) -> Self {
+            srgb::encode_u8(linear)
+        }
+
+        fn widen(self) -> u16 {
+            self as u16 * 257
+        }
+
+        fn narrow(linear: u16) -> Self {
+            ((linear as u32 + 128) / 257) as u8
+        }
+
+        fn mix(self, other: Self, t: f32) -> Self {
+            (self as f32 + (other as f32 - self as f32) * t).round() as u8
+        }
+    }
+
+    // Sums are kept in 64 bits so they don't depend on the width of `usize`
+    impl Sealed for u16 {
+        type Sum = u64;
+        type Linear = f32;
+
+        const MAX: u64 = u16::MAX as u64;
+        const SUM_MAX: u64 = u64::MAX;
+        const BYTES: bool = false;
+
+        #[inline(always)]
+        fn weighted(self, weight: usize) -> u64 {
+            self as u64 * weight as u64
+        }
+
+        #[inline(always)]
+        fn mul_shr(sum: u64, mul: usize, shg: usize) -> Self {
+            ((sum * mul as u64) >> shg) as u16
+        }
+
+        #[inline(always)]
+        fn divide(sum: u64, weight: usize) -> Self {
+            (sum / weight as u64) as u16
+        }
+
+        fn bytes_mut(_: &mut [Self]) -> Option<&mut [u8]> {
+            None
+        }
+
+        fn premultiply(self, alpha: Self) -> Self {
+            ((self as u64 * alpha as u64 + 32767) / 65535) as u16
+        }
+
+        fn unpremultiply(self, alpha: Self) -> Self {
+            match alpha {
+                0 => 0,
+                a => ((self as u64 * 65535 + a as u64 / 2) / a as u64).min(65535) as u16,
+            }
+        }
+
+        fn decode_srgb(self) -> f32 {
+            srgb::decode_u16(self)
+        }
+
+        fn encode_srgb(linear: f32) -> Self {
+            (srgb::encode(linear as f64) * 65535.0).round() as u16
+        }
+
+        fn widen(self) -> f32 {
+            self as f32 / 65535.0
+        }
+
+        fn narrow(linear: f32) -> Self {
+            (linear * 65535.0).round() as u16
+        }
+
+        fn mix(self, other: Self, t: f32) -> Self {
+            (self as f32 + (other as f32 - self as f32) * t).round() as u16
+        }
+    }
+
+    // Sums are kept in 64 bits, so rounding errors in the running sums stay
+    // well below the precision of the samples. A NaN or infinite sample spreads
+    // along the rest of its line rather than just its window.
+    impl Sealed for f32 {
+        type Sum = f64;
+        type Linear = f32;
+
+        const MAX: u64 = 1;
+        const SUM_MAX: u64 = u64::MAX;
+        const BYTES: bool = false;
+
+        #[inline(always)]
+        fn weighted(self, weight: usize) -> f64 {
+            self as f64 * weight as f64
+        }
+
+        fn mul_shr(_: f64, _: usize, _: usize) -> Self {
+            unreachable!("only bytes use MUL_TABLE")
+        }
+
+        #[inline(always)]
+        fn divide(sum: f64, weight: usize) -> Self {
+            (sum / weight as f64) as f32
+        }
+
+        fn bytes_mut(_: &mut [Self]) -> Option<&mut [u8]> {
+            None
+        }
+
+        fn premultiply(self, alpha: Self) -> Self {
+            self * alpha
+        }
+
+        fn unpremultiply(self, alpha: Self) -> Self {
+            if alpha == 0.0 {
+                0.0
+            } else {
+                self / alpha
+            }
+        }
+
+        fn decode_srgb(self) -> f32 {
+            srgb::decode(self as f64) as f32
+        }
+
+        fn encode_srgb(linear: f32) -> Self {
+            srgb::encode(linear as f64) as f32
+        }
+
+        fn widen(self) -> f32 {
+            self
+        }
+
+        fn narrow(linear: f32) -> Self {
+            linear
+        }
+
+        fn mix(self, other: Self, t: f32) -> Self {
+            self + (other - self) * t
+        }
+    }
+}
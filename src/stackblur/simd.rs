@@ -0,0 +1,361 @@
+use super::{Scale, StackProcessor};
+
+// Kernels for the RGBA line blur. All four channels are summed in the lanes of
+// one vector register. Every sum fits in 32 bits for the radii covered by
+// MUL_TABLE and the multiply by `mul` is done in 64 bits, so the output is
+// bit-exact with the scalar path. Larger radii, lines no longer than the radius
+// and lines leaving some channels untouched always take the scalar path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Kernel {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Sse2,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+    #[cfg(target_arch = "aarch64")]
+    Neon,
+}
+
+impl Kernel {
+    // Best kernel for the running CPU
+    pub(super) fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Kernel::Avx2;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return Kernel::Sse2;
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                return Kernel::Neon;
+            }
+        }
+        Kernel::Scalar
+    }
+}
+
+pub(super) fn blur_line(
+    kernel: Kernel,
+    processor: &mut StackProcessor<u8, 4>,
+    data: &mut [u8],
+    start: usize,
+    step: usize,
+    len: usize,
+) {
+    let kernel = match processor.scale {
+        Scale::Table { .. } if processor.blurred == [true; 4] && processor.r < len => kernel,
+        _ => Kernel::Scalar,
+    };
+    // Safety: `detect` only hands out kernels the CPU supports
+    match kernel {
+        Kernel::Scalar => processor.blur_line(data, start, step, len),
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Sse2 => unsafe { x86::blur_line_sse2(processor, data, start, step, len) },
+        #[cfg(target_arch = "x86_64")]
+        Kernel::Avx2 => unsafe { x86::blur_line_avx2(processor, data, start, step, len) },
+        #[cfg(target_arch = "aarch64")]
+        Kernel::Neon => unsafe { neon::blur_line(processor, data, start, step, len) },
+    }
+}
+
+// Seeds the processor for a line, with the sums in 32-bit lanes
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn seed(
+    processor: &mut StackProcessor<u8, 4>,
+    data: &[u8],
+    start: usize,
+    step: usize,
+    len: usize,
+) -> [[u32; 4]; 3] {
+    let sums = processor.seed(data, start, step, len);
+    sums.map(|sum| sum.map(|c| c as u32))
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::{seed, Scale, StackProcessor};
+    use std::arch::x86_64::*;
+
+    pub(super) unsafe fn blur_line_sse2(
+        processor: &mut StackProcessor<u8, 4>,
+        data: &mut [u8],
+        start: usize,
+        step: usize,
+        len: usize,
+    ) {
+        blur_line::<false>(processor, data, start, step, len)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn blur_line_avx2(
+        processor: &mut StackProcessor<u8, 4>,
+        data: &mut [u8],
+        start: usize,
+        step: usize,
+        len: usize,
+    ) {
+        blur_line::<true>(processor, data, start, step, len)
+    }
+
+    #[inline(always)]
+    unsafe fn blur_line<const AVX2: bool>(
+        processor: &mut StackProcessor<u8, 4>,
+        data: &mut [u8],
+        start: usize,
+        step: usize,
+        len: usize,
+    ) {
+        let pixel = |data: &[u8], i: usize| -> [u8; 4] {
+            let i = start + step * i;
+            [data[i], data[i + 1], data[i + 2], data[i + 3]]
+        };
+
+        let [sum, sum_in, sum_out] = seed(processor, data, start, step, len);
+        let mut sum = load(sum);
+        let mut sum_in = load(sum_in);
+        let mut sum_out = load(sum_out);
+
+        let (r, div) = (processor.r, processor.div);
+        let Scale::Table { mul, shg } = processor.scale else {
+            unreachable!("only table radii reach the vector kernels")
+        };
+        let mul = mul as u32;
+        let shg = _mm_cvtsi32_si128(shg as i32);
+        let mut stack_ptr = r;
+
+        for x in 0..len {
+            let i = start + step * x;
+            let out = if AVX2 {
+                mul_shr_avx2(sum, mul, shg)
+            } else {
+                mul_shr_sse2(sum, mul, shg)
+            };
+            data[i..i + 4].copy_from_slice(&narrow(out));
+
+            sum = _mm_sub_epi32(sum, sum_out);
+
+            let stack_start = (stack_ptr + r + 1) % div;
+            sum_out = _mm_sub_epi32(sum_out, widen(processor.stack[stack_start]));
+
+            let p = match x + r + 1 {
+                next if next < len => pixel(data, next),
+                next => processor.after[next - len],
+            };
+            processor.stack[stack_start] = p;
+            sum_in = _mm_add_epi32(sum_in, widen(p));
+            sum = _mm_add_epi32(sum, sum_in);
+
+            stack_ptr = (stack_ptr + 1) % div;
+            let centre = widen(processor.stack[stack_ptr]);
+            sum_out = _mm_add_epi32(sum_out, centre);
+            sum_in = _mm_sub_epi32(sum_in, centre);
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn load(v: [u32; 4]) -> __m128i {
+        _mm_setr_epi32(v[0] as i32, v[1] as i32, v[2] as i32, v[3] as i32)
+    }
+
+    #[inline(always)]
+    unsafe fn widen(p: [u8; 4]) -> __m128i {
+        let zero = _mm_setzero_si128();
+        let v = _mm_cvtsi32_si128(i32::from_le_bytes(p));
+        _mm_unpacklo_epi16(_mm_unpacklo_epi8(v, zero), zero)
+    }
+
+    #[inline(always)]
+    unsafe fn narrow(v: __m128i) -> [u8; 4] {
+        let v = _mm_packs_epi32(v, v);
+        _mm_cvtsi128_si32(_mm_packus_epi16(v, v)).to_le_bytes()
+    }
+
+    // `(sum * mul) >> shg` per lane. SSE2 only multiplies the even lanes into
+    // 64 bits, so the odd lanes are shifted down and multiplied separately.
+    #[inline(always)]
+    unsafe fn mul_shr_sse2(sum: __m128i, mul: u32, shg: __m128i) -> __m128i {
+        let mul = _mm_set1_epi32(mul as i32);
+        let even = _mm_srl_epi64(_mm_mul_epu32(sum, mul), shg);
+        let odd = _mm_srl_epi64(_mm_mul_epu32(_mm_srli_epi64(sum, 32), mul), shg);
+        _mm_or_si128(even, _mm_slli_epi64(odd, 32))
+    }
+
+    // `(sum * mul) >> shg` per lane, with all four lanes widened to 64 bits at once
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    unsafe fn mul_shr_avx2(sum: __m128i, mul: u32, shg: __m128i) -> __m128i {
+        let products = _mm256_mul_epu32(_mm256_cvtepu32_epi64(sum), _mm256_set1_epi64x(mul as i64));
+        let shifted = _mm256_srl_epi64(products, shg);
+        let low_halves = _mm256_setr_epi32(0, 2, 4, 6, 0, 2, 4, 6);
+        _mm256_castsi256_si128(_mm256_permutevar8x32_epi32(shifted, low_halves))
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::{seed, Scale, StackProcessor};
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn blur_line(
+        processor: &mut StackProcessor<u8, 4>,
+        data: &mut [u8],
+        start: usize,
+        step: usize,
+        len: usize,
+    ) {
+        let pixel = |data: &[u8], i: usize| -> [u8; 4] {
+            let i = start + step * i;
+            [data[i], data[i + 1], data[i + 2], data[i + 3]]
+        };
+
+        let [sum, sum_in, sum_out] = seed(processor, data, start, step, len);
+        let mut sum = vld1q_u32(sum.as_ptr());
+        let mut sum_in = vld1q_u32(sum_in.as_ptr());
+        let mut sum_out = vld1q_u32(sum_out.as_ptr());
+
+        let (r, div) = (processor.r, processor.div);
+        let Scale::Table { mul, shg } = processor.scale else {
+            unreachable!("only table radii reach the vector kernels")
+        };
+        let mul = mul as u32;
+        let shg = vdupq_n_s64(-(shg as i64));
+        let mut stack_ptr = r;
+
+        for x in 0..len {
+            let i = start + step * x;
+            let low = vshlq_u64(vmull_n_u32(vget_low_u32(sum), mul), shg);
+            let high = vshlq_u64(vmull_n_u32(vget_high_u32(sum), mul), shg);
+            let out = vcombine_u32(vmovn_u64(low), vmovn_u64(high));
+            data[i..i + 4].copy_from_slice(&narrow(out));
+
+            sum = vsubq_u32(sum, sum_out);
+
+            let stack_start = (stack_ptr + r + 1) % div;
+            sum_out = vsubq_u32(sum_out, widen(processor.stack[stack_start]));
+
+            let p = match x + r + 1 {
+                next if next < len => pixel(data, next),
+                next => processor.after[next - len],
+            };
+            processor.stack[stack_start] = p;
+            sum_in = vaddq_u32(sum_in, widen(p));
+            sum = vaddq_u32(sum, sum_in);
+
+            stack_ptr = (stack_ptr + 1) % div;
+            let centre = widen(processor.stack[stack_ptr]);
+            sum_out = vaddq_u32(sum_out, centre);
+            sum_in = vsubq_u32(sum_in, centre);
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn widen(p: [u8; 4]) -> uint32x4_t {
+        let bytes = vcreate_u8(u32::from_le_bytes(p) as u64);
+        vmovl_u16(vget_low_u16(vmovl_u8(bytes)))
+    }
+
+    #[inline(always)]
+    unsafe fn narrow(v: uint32x4_t) -> [u8; 4] {
+        let bytes = vmovn_u16(vcombine_u16(vmovn_u32(v), vdup_n_u16(0)));
+        vget_lane_u32::<0>(vreinterpret_u32_u8(bytes)).to_le_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Edge, StackProcessor};
+    use super::{blur_line, Kernel};
+
+    fn kernels() -> Vec<Kernel> {
+        let mut kernels = vec![];
+        #[cfg(target_arch = "x86_64")]
+        {
+            kernels.push(Kernel::Sse2);
+            if is_x86_feature_detected!("avx2") {
+                kernels.push(Kernel::Avx2);
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                kernels.push(Kernel::Neon);
+            }
+        }
+        kernels
+    }
+
+    // Both passes of a full blur, run with a given kernel
+    fn blur(
+        data: &[u8],
+        width: usize,
+        height: usize,
+        radius: usize,
+        edge: Edge,
+        kernel: Kernel,
+    ) -> Vec<u8> {
+        let mut out = data.to_vec();
+        let mut processor = StackProcessor::<u8, 4>::new(radius, edge, u64::MAX);
+        for row in 0..height {
+            blur_line(kernel, &mut processor, &mut out, row * width * 4, 4, width);
+        }
+        for col in 0..width {
+            blur_line(kernel, &mut processor, &mut out, col * 4, width * 4, height);
+        }
+        out
+    }
+
+    #[test]
+    fn kernels_match_scalar() {
+        let (width, height) = (67, 41);
+        let mut state = 0x2545_f491u32;
+        let data: Vec<u8> = (0..width * height * 4)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+
+        let edges = [
+            Edge::Clamp,
+            Edge::Mirror,
+            Edge::Wrap,
+            Edge::Constant([10, 200, 0, 255]),
+        ];
+        for (radius, edge) in [1, 2, 3, 8, 33, 100, 255, 256]
+            .into_iter()
+            .flat_map(|radius| edges.map(|edge| (radius, edge)))
+        {
+            let expected = blur(&data, width, height, radius, edge, Kernel::Scalar);
+            for kernel in kernels() {
+                let actual = blur(&data, width, height, radius, edge, kernel);
+                assert!(
+                    expected == actual,
+                    "{:?} differs at radius {} with {:?}",
+                    kernel,
+                    radius,
+                    edge
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn kernels_match_scalar_on_saturated_input() {
+        let (width, height) = (300, 2);
+        let data = vec![255; width * height * 4];
+        for kernel in kernels() {
+            assert!(
+                blur(&data, width, height, 256, Edge::Clamp, kernel) == data,
+                "{:?}",
+                kernel
+            );
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::OnceLock;
+
+/// sRGB encoded value to linear light, both nominally in `0.0..=1.0`
+pub(super) fn decode(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear light to sRGB encoded value, the inverse of `decode`
+pub(super) fn encode(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+// Linear light of every byte, scaled to 16 bits so the darkest levels stay
+// apart
+pub(super) fn decode_u8(v: u8) -> u16 {
+    static TABLE: OnceLock<Vec<u16>> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        (0..=255)
+            .map(|v| (decode(v as f64 / 255.0) * 65535.0).round() as u16)
+            .collect()
+    });
+    table[v as usize]
+}
+
+// Nearest byte to every 16-bit linear light level
+pub(super) fn encode_u8(v: u16) -> u8 {
+    static TABLE: OnceLock<Vec<u8>> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        (0..=65535)
+            .map(|v| (encode(v as f64 / 65535.0) * 255.0).round() as u8)
+            .collect()
+    });
+    table[v as usize]
+}
+
+// Linear light of every 16-bit level
+pub(super) fn decode_u16(v: u16) -> f32 {
+    static TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        (0..=65535)
+            .map(|v| decode(v as f64 / 65535.0) as f32)
+            .collect()
+    });
+    table[v as usize]
+}